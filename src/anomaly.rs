@@ -0,0 +1,591 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use flodbadd::{analyzer::SessionAnalyzer, sessions::SessionInfo};
+use serde::{Deserialize, Serialize};
+
+use crate::report::Annotations;
+
+/// Bumped when the saved model layout changes; older files are ignored.
+const MODEL_VERSION: u32 = 1;
+
+/// Sessions kept in a saved model. Oldest sessions are dropped first.
+const MAX_MODEL_SESSIONS: usize = 20_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyLevel {
+    /// Sessions the analyzer scores as suspicious or abnormal.
+    Suspicious,
+    /// Only sessions the analyzer reports as anomalous (the default).
+    Abnormal,
+}
+
+/// How anomalies are turned into violations by `scan` and `watch`.
+#[derive(Debug, Clone)]
+pub struct AnomalyOptions {
+    /// Saved analyzer state, loaded at start and written back at the end.
+    pub model: Option<String>,
+    pub level: AnomalyLevel,
+    /// Anomalies tolerated before they count as a violation.
+    pub min_count: usize,
+    /// Anomalies in sessions started within this period of the run start
+    /// are reported but not enforced.
+    pub warmup: Option<Duration>,
+}
+
+impl Default for AnomalyOptions {
+    fn default() -> Self {
+        AnomalyOptions {
+            model: None,
+            level: AnomalyLevel::Abnormal,
+            min_count: 1,
+            warmup: None,
+        }
+    }
+}
+
+/// Analyzer state persisted between runs. Flodbadd's analyzer trains on the
+/// sessions it sees, so the state is the recent session history that is
+/// replayed into a fresh analyzer before the new traffic is scored.
+#[derive(Serialize, Deserialize)]
+struct SavedModel {
+    version: u32,
+    saved_at: DateTime<Utc>,
+    sessions: Vec<SessionInfo>,
+}
+
+/// Session history of the current run's model.
+#[derive(Default)]
+pub struct Model {
+    history: Vec<SessionInfo>,
+    /// Sessions replayed from the saved model. The analyzer may report them
+    /// again; they belong to earlier runs and are never enforced.
+    replayed: HashSet<String>,
+}
+
+/// Anomalies split by whether they count towards a violation.
+#[derive(Default)]
+pub struct Anomalies {
+    pub enforced: Vec<SessionInfo>,
+    /// Seen during the warm-up period, or below `min_count`.
+    pub recorded: Vec<SessionInfo>,
+}
+
+impl AnomalyOptions {
+    /// Sessions of the saved model, or nothing when there is no usable model.
+    pub fn load_model(&self) -> Result<Vec<SessionInfo>> {
+        let Some(path) = &self.model else {
+            return Ok(Vec::new());
+        };
+        if !std::path::Path::new(path).exists() {
+            println!("Anomaly model '{}' not found, starting cold", path);
+            return Ok(Vec::new());
+        }
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read anomaly model '{}'", path))?;
+        let model: SavedModel = serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse anomaly model '{}'", path))?;
+        if model.version != MODEL_VERSION {
+            println!(
+                "Anomaly model '{}' has version {} (expected {}), starting cold",
+                path, model.version, MODEL_VERSION
+            );
+            return Ok(Vec::new());
+        }
+        println!(
+            "Loaded anomaly model '{}' ({} sessions, saved {})",
+            path,
+            model.sessions.len(),
+            model.saved_at.to_rfc3339()
+        );
+        Ok(model.sessions)
+    }
+
+    /// Loads the saved model and replays it into `analyzer`.
+    pub async fn warm_analyzer(&self, analyzer: &SessionAnalyzer) -> Result<Model> {
+        let history = self.load_model()?;
+        if !history.is_empty() {
+            let mut replay = history.clone();
+            analyzer.analyze_sessions(&mut replay).await;
+        }
+        Ok(Model {
+            replayed: history.iter().map(|s| s.uid.clone()).collect(),
+            history,
+        })
+    }
+
+    /// Adds the `analyzed` sessions to the model, keeping the most recent
+    /// sessions. Anomalies are left out, including those held back by the
+    /// warm-up or `min_count`, so they are not learned as normal traffic.
+    pub fn learn(&self, model: &mut Model, analyzed: &[SessionInfo], anomalies: &Anomalies) {
+        if self.model.is_none() {
+            return;
+        }
+        let flagged: HashSet<&str> = anomalies
+            .enforced
+            .iter()
+            .chain(&anomalies.recorded)
+            .map(|s| s.uid.as_str())
+            .collect();
+        let updated: HashSet<&str> = analyzed.iter().map(|s| s.uid.as_str()).collect();
+        let history = &mut model.history;
+        history.retain(|s| !updated.contains(s.uid.as_str()));
+        history.extend(
+            analyzed
+                .iter()
+                .filter(|s| !flagged.contains(s.uid.as_str()))
+                .cloned(),
+        );
+        if history.len() > MAX_MODEL_SESSIONS {
+            history.drain(..history.len() - MAX_MODEL_SESSIONS);
+        }
+    }
+
+    /// Writes the model out.
+    pub fn save_model(&self, model: &Model) -> Result<()> {
+        let Some(path) = &self.model else {
+            return Ok(());
+        };
+        let history = &model.history;
+        let model = SavedModel {
+            version: MODEL_VERSION,
+            saved_at: Utc::now(),
+            sessions: history.clone(),
+        };
+        std::fs::write(path, serde_json::to_string(&model)?)
+            .with_context(|| format!("Failed to write anomaly model '{}'", path))?;
+        tracing::info!(
+            "Saved anomaly model '{}' ({} sessions)",
+            path,
+            history.len()
+        );
+        Ok(())
+    }
+
+    /// Picks the anomalies at the configured level from the analyzer's
+    /// `anomalous` sessions and the `analyzed` sessions' criticality tags,
+    /// then holds back those inside the warm-up period and, when fewer than
+    /// `min_count` remain, all of them.
+    pub fn classify(
+        &self,
+        model: &Model,
+        analyzed: &[SessionInfo],
+        anomalous: Vec<SessionInfo>,
+        run_start: DateTime<Utc>,
+    ) -> Anomalies {
+        let mut selected: Vec<SessionInfo> = anomalous
+            .into_iter()
+            .filter(|s| !model.replayed.contains(&s.uid))
+            .collect();
+        if self.level == AnomalyLevel::Suspicious {
+            let mut seen: HashSet<String> = selected.iter().map(|s| s.uid.clone()).collect();
+            selected.extend(
+                analyzed
+                    .iter()
+                    .filter(|s| is_suspicious(&s.criticality) && seen.insert(s.uid.clone()))
+                    .cloned(),
+            );
+        }
+
+        let mut anomalies = Anomalies::default();
+        let warmup_end = self.warmup.map(|w| run_start + w);
+        for session in selected {
+            if warmup_end.is_some_and(|end| session.stats.start_time < end) {
+                anomalies.recorded.push(session);
+            } else {
+                anomalies.enforced.push(session);
+            }
+        }
+        if anomalies.enforced.len() < self.min_count {
+            anomalies.recorded.append(&mut anomalies.enforced);
+        }
+        anomalies
+    }
+}
+
+impl Anomalies {
+    /// The anomalies whose uid is not in `explained` yet, adding them to it.
+    pub fn unexplained(&self, explained: &mut HashSet<String>) -> Anomalies {
+        let mut pick = |sessions: &[SessionInfo]| -> Vec<SessionInfo> {
+            sessions
+                .iter()
+                .filter(|s| explained.insert(s.uid.clone()))
+                .cloned()
+                .collect()
+        };
+        Anomalies {
+            enforced: pick(&self.enforced),
+            recorded: pick(&self.recorded),
+        }
+    }
+}
+
+impl Model {
+    /// Sessions the current traffic is compared against: the saved history
+    /// plus this run's sessions that were not enforced as anomalies.
+    pub fn baseline<'a>(
+        &'a self,
+        analyzed: &'a [SessionInfo],
+        anomalies: &Anomalies,
+    ) -> Vec<&'a SessionInfo> {
+        let flagged: HashSet<&str> = anomalies
+            .enforced
+            .iter()
+            .chain(&anomalies.recorded)
+            .map(|s| s.uid.as_str())
+            .collect();
+        let current: HashSet<&str> = analyzed.iter().map(|s| s.uid.as_str()).collect();
+        self.history
+            .iter()
+            .filter(|s| !current.contains(s.uid.as_str()))
+            .chain(analyzed)
+            .filter(|s| !flagged.contains(s.uid.as_str()))
+            .collect()
+    }
+}
+
+/// Percentile above (or, for the byte ratio, below) which a numeric feature
+/// is reported as unusual.
+const HIGH_PERCENTILE: f64 = 0.99;
+const LOW_PERCENTILE: f64 = 0.01;
+
+/// Share of baseline sessions under which a destination port is rare.
+const RARE_PORT_SHARE: f64 = 0.01;
+
+/// Baseline sessions needed before numeric features are compared.
+const MIN_BASELINE: usize = 20;
+
+/// The session properties anomaly explanations are based on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Features {
+    pub port: u16,
+    pub process: Option<String>,
+    pub outbound_bytes: u64,
+    /// Outbound bytes per inbound byte.
+    pub byte_ratio: f64,
+    pub duration_secs: f64,
+}
+
+impl Features {
+    pub fn of(session: &SessionInfo) -> Self {
+        let stats = &session.stats;
+        let end = stats.end_time.unwrap_or(stats.last_activity);
+        Features {
+            port: session.session.dst_port,
+            process: session.l7.as_ref().map(|l7| l7.process_name.clone()),
+            outbound_bytes: stats.outbound_bytes,
+            byte_ratio: stats.outbound_bytes as f64 / stats.inbound_bytes.max(1) as f64,
+            duration_secs: (end - stats.start_time).num_milliseconds().max(0) as f64 / 1000.0,
+        }
+    }
+}
+
+/// One feature that sets a session apart from the baseline.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Factor {
+    pub feature: &'static str,
+    pub detail: String,
+}
+
+/// Fraction of `values` strictly below `value`.
+fn percentile(values: &[f64], value: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().filter(|v| **v < value).count() as f64 / values.len() as f64
+}
+
+fn quantile(values: &[f64], q: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted
+        .get(((sorted.len() as f64 - 1.0) * q).round() as usize)
+        .copied()
+        .unwrap_or(0.0)
+}
+
+/// A numeric feature compared against baseline percentiles.
+struct NumericFeature {
+    name: &'static str,
+    label: &'static str,
+    value: fn(&Features) -> f64,
+    /// Also unusual when far below the baseline.
+    two_sided: bool,
+}
+
+const NUMERIC_FEATURES: &[NumericFeature] = &[
+    NumericFeature {
+        name: "byte ratio",
+        label: "outbound/inbound ratio",
+        value: |f| f.byte_ratio,
+        two_sided: true,
+    },
+    NumericFeature {
+        name: "outbound volume",
+        label: "outbound bytes",
+        value: |f| f.outbound_bytes as f64,
+        two_sided: false,
+    },
+    NumericFeature {
+        name: "timing",
+        label: "duration (s)",
+        value: |f| f.duration_secs,
+        two_sided: false,
+    },
+];
+
+/// The features of `session` that deviate from `baseline`: rare port, new
+/// process, unusual byte ratio, outbound volume or duration.
+pub fn explain(session: &Features, baseline: &[Features]) -> Vec<Factor> {
+    let mut factors = Vec::new();
+    let total = baseline.len();
+
+    let port_count = baseline.iter().filter(|b| b.port == session.port).count();
+    if total > 0 && (port_count as f64) < total as f64 * RARE_PORT_SHARE.max(1.0 / total as f64) {
+        factors.push(Factor {
+            feature: "rare port",
+            detail: format!(
+                "port {} seen in {} of {} baseline sessions (threshold {}%)",
+                session.port,
+                port_count,
+                total,
+                RARE_PORT_SHARE * 100.0
+            ),
+        });
+    }
+
+    if let Some(process) = &session.process {
+        if total > 0 && !baseline.iter().any(|b| b.process.as_ref() == Some(process)) {
+            factors.push(Factor {
+                feature: "new process",
+                detail: format!("{} not seen in {} baseline sessions", process, total),
+            });
+        }
+    }
+
+    if total < MIN_BASELINE {
+        return factors;
+    }
+    for numeric in NUMERIC_FEATURES {
+        let values: Vec<f64> = baseline.iter().map(numeric.value).collect();
+        let value = (numeric.value)(session);
+        let rank = percentile(&values, value);
+        let detail = if rank >= HIGH_PERCENTILE {
+            format!(
+                "{} {:.2} above p{} of baseline ({:.2})",
+                numeric.label,
+                value,
+                HIGH_PERCENTILE * 100.0,
+                quantile(&values, HIGH_PERCENTILE)
+            )
+        } else if numeric.two_sided
+            && rank <= LOW_PERCENTILE
+            && value < quantile(&values, LOW_PERCENTILE)
+        {
+            format!(
+                "{} {:.2} below p{} of baseline ({:.2})",
+                numeric.label,
+                value,
+                LOW_PERCENTILE * 100.0,
+                quantile(&values, LOW_PERCENTILE)
+            )
+        } else {
+            continue;
+        };
+        factors.push(Factor {
+            feature: numeric.name,
+            detail,
+        });
+    }
+    factors
+}
+
+/// The analyzer's verdict for a session: the value of the `anomaly:`
+/// criticality tag it set, if any.
+pub fn verdict(criticality: &str) -> Option<&str> {
+    criticality
+        .split(',')
+        .map(str::trim)
+        .find_map(|tag| tag.strip_prefix("anomaly:"))
+}
+
+/// Why a session was reported as anomalous. Flodbadd's analyzer exposes no
+/// score, only the criticality tag it sets, so `verdict` is all it computed;
+/// `heuristic_factors` come from flodviddar comparing the session with the
+/// baseline and are not the analyzer's own inputs.
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub verdict: Option<String>,
+    /// Whether the anomaly counts towards a violation.
+    pub enforced: bool,
+    /// The configured `--anomaly-level`.
+    pub level: &'static str,
+    pub min_count: usize,
+    pub baseline_sessions: usize,
+    pub heuristic_factors: Vec<Factor>,
+}
+
+/// Explains each enforced and recorded anomaly against `baseline`.
+pub fn explanations<'a>(
+    options: &AnomalyOptions,
+    anomalies: &'a Anomalies,
+    baseline: &[&SessionInfo],
+) -> Vec<(&'a SessionInfo, Explanation)> {
+    let baseline: Vec<Features> = baseline.iter().map(|s| Features::of(s)).collect();
+    let level = match options.level {
+        AnomalyLevel::Suspicious => "suspicious",
+        AnomalyLevel::Abnormal => "abnormal",
+    };
+    anomalies
+        .enforced
+        .iter()
+        .map(|s| (s, true))
+        .chain(anomalies.recorded.iter().map(|s| (s, false)))
+        .map(|(session, enforced)| {
+            let explanation = Explanation {
+                verdict: verdict(&session.criticality).map(str::to_string),
+                enforced,
+                level,
+                min_count: options.min_count,
+                baseline_sessions: baseline.len(),
+                heuristic_factors: explain(&Features::of(session), &baseline),
+            };
+            (session, explanation)
+        })
+        .collect()
+}
+
+/// Prints why each anomalous session was reported: the analyzer's verdict,
+/// the configured level and the heuristic factors.
+pub fn print_explanations(
+    options: &AnomalyOptions,
+    anomalies: &Anomalies,
+    baseline: &[&SessionInfo],
+) {
+    let explanations = explanations(options, anomalies, baseline);
+    let Some((_, first)) = explanations.first() else {
+        return;
+    };
+    println!("\n=== Anomaly Explanations ===");
+    println!(
+        "Level: {} (min count {}). The analyzer reports a verdict, not a score.",
+        first.level, first.min_count
+    );
+    println!(
+        "Heuristic factors compare each session with a baseline of {} sessions; \
+         they are not the analyzer's inputs.",
+        first.baseline_sessions
+    );
+    for (session, explanation) in &explanations {
+        println!(
+            "[verdict {}, {}] {}:{} -> {}:{} ({})",
+            explanation.verdict.as_deref().unwrap_or("untagged"),
+            if explanation.enforced {
+                "enforced"
+            } else {
+                "recorded"
+            },
+            session.session.src_ip,
+            session.session.src_port,
+            session
+                .dst_domain
+                .clone()
+                .unwrap_or_else(|| session.session.dst_ip.to_string()),
+            session.session.dst_port,
+            session
+                .l7
+                .as_ref()
+                .map(|l7| l7.process_name.as_str())
+                .unwrap_or("unknown process"),
+        );
+        if explanation.heuristic_factors.is_empty() {
+            println!("    - no heuristic factor stands out");
+        }
+        for factor in &explanation.heuristic_factors {
+            println!("    - {}: {}", factor.feature, factor.detail);
+        }
+    }
+}
+
+/// Adds the explanation of each anomaly to the JSON report as `anomaly`.
+pub fn annotate(
+    options: &AnomalyOptions,
+    anomalies: &Anomalies,
+    baseline: &[&SessionInfo],
+    annotations: &mut Annotations,
+) -> Result<()> {
+    for (session, explanation) in explanations(options, anomalies, baseline) {
+        annotations.add(session, "anomaly", &explanation)?;
+    }
+    Ok(())
+}
+
+/// Whether a session's criticality tags (e.g. `anomaly:suspicious`) mark it
+/// as suspicious or worse.
+pub fn is_suspicious(criticality: &str) -> bool {
+    criticality
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "anomaly:suspicious" || tag == "anomaly:abnormal")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn criticality_tags() {
+        assert!(is_suspicious("anomaly:suspicious"));
+        assert!(is_suspicious("blacklist:firehol_level1,anomaly:abnormal"));
+        assert!(!is_suspicious("anomaly:normal"));
+        assert!(!is_suspicious(""));
+    }
+
+    fn features(port: u16, process: &str, ratio: f64) -> Features {
+        Features {
+            port,
+            process: Some(process.to_string()),
+            outbound_bytes: 2_000,
+            byte_ratio: ratio,
+            duration_secs: 3.0,
+        }
+    }
+
+    #[test]
+    fn explanations_name_deviating_features() {
+        let baseline: Vec<Features> = (0..100)
+            .map(|i| features(443, "cargo", 0.05 + i as f64 / 1000.0))
+            .collect();
+        let normal = features(443, "cargo", 0.08);
+        assert!(explain(&normal, &baseline).is_empty());
+
+        let exfil = features(8443, "python3", 40.0);
+        let names: Vec<&str> = explain(&exfil, &baseline)
+            .iter()
+            .map(|f| f.feature)
+            .collect();
+        assert_eq!(names, vec!["rare port", "new process", "byte ratio"]);
+
+        // Too few baseline sessions for numeric comparisons.
+        let names: Vec<&str> = explain(&exfil, &baseline[..5])
+            .iter()
+            .map(|f| f.feature)
+            .collect();
+        assert_eq!(names, vec!["rare port", "new process"]);
+        assert_eq!(
+            verdict("blacklist:firehol_level1, anomaly:suspicious"),
+            Some("suspicious")
+        );
+        assert_eq!(verdict("blacklist:firehol_level1"), None);
+    }
+
+    #[test]
+    fn defaults_enforce_every_anomaly() {
+        let options = AnomalyOptions::default();
+        let anomalies = options.classify(&Model::default(), &[], Vec::new(), Utc::now());
+        assert!(anomalies.enforced.is_empty() && anomalies.recorded.is_empty());
+        assert_eq!(options.min_count, 1);
+        assert_eq!(options.level, AnomalyLevel::Abnormal);
+    }
+}
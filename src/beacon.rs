@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use flodbadd::sessions::SessionInfo;
+
+use crate::policy::{is_egress, Action, PolicyViolation, UNRESOLVED_DOMAINS};
+use crate::whitelist::{domain_matches, ip_matches};
+
+/// Beaconing detection settings for `watch`.
+#[derive(Debug, Clone)]
+pub struct BeaconOptions {
+    pub action: Action,
+    /// Connections to a destination needed before its timing is judged.
+    pub min_count: usize,
+    /// Highest jitter, relative to the period, that still counts as
+    /// periodic.
+    pub max_jitter: f64,
+    /// Shorter periods are ignored: they are polling or retries rather
+    /// than C2 check-ins.
+    pub min_period: Duration,
+    /// Domains (wildcards allowed) and IPs/CIDRs expected to be periodic,
+    /// such as NTP or update checks.
+    pub allow: Vec<String>,
+}
+
+impl Default for BeaconOptions {
+    fn default() -> Self {
+        BeaconOptions {
+            action: Action::Warn,
+            min_count: 6,
+            max_jitter: 0.1,
+            min_period: Duration::seconds(10),
+            allow: Vec::new(),
+        }
+    }
+}
+
+/// Timing of a series of connections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Periodicity {
+    /// Median interval between connections, in seconds.
+    pub period: f64,
+    /// Median deviation of the intervals from the period, relative to it.
+    /// Medians keep a missed or delayed check-in from hiding the pattern.
+    pub jitter: f64,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let middle = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[middle]
+    } else {
+        (values[middle - 1] + values[middle]) / 2.0
+    }
+}
+
+/// Period and jitter of connections started at `starts` (sorted), or `None`
+/// with fewer than three connections.
+pub fn periodicity(starts: &[DateTime<Utc>]) -> Option<Periodicity> {
+    if starts.len() < 3 {
+        return None;
+    }
+    let mut intervals: Vec<f64> = starts
+        .windows(2)
+        .map(|w| (w[1] - w[0]).num_milliseconds() as f64 / 1000.0)
+        .collect();
+    let period = median(&mut intervals);
+    if period <= 0.0 {
+        return None;
+    }
+    let mut deviations: Vec<f64> = intervals.iter().map(|i| (i - period).abs()).collect();
+    Some(Periodicity {
+        period,
+        jitter: median(&mut deviations) / period,
+    })
+}
+
+/// Connections to one destination port by one process.
+struct Series {
+    label: String,
+    starts: Vec<DateTime<Utc>>,
+    last: SessionInfo,
+}
+
+/// Accumulates session start times across `watch` polls and flags
+/// destinations contacted at a regular interval.
+pub struct BeaconDetector {
+    options: BeaconOptions,
+    seen: HashSet<String>,
+    series: HashMap<String, Series>,
+    reported: HashSet<String>,
+}
+
+impl BeaconDetector {
+    pub fn new(options: BeaconOptions) -> Self {
+        BeaconDetector {
+            options,
+            seen: HashSet::new(),
+            series: HashMap::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    fn is_allowed(&self, session: &SessionInfo) -> bool {
+        self.options.allow.iter().any(|pattern| {
+            ip_matches(pattern, &session.session.dst_ip)
+                || session
+                    .dst_domain
+                    .as_deref()
+                    .is_some_and(|d| domain_matches(pattern, d))
+        })
+    }
+
+    /// Records the egress sessions not seen in earlier polls.
+    pub fn observe(&mut self, sessions: &[SessionInfo]) {
+        if self.options.action == Action::Off {
+            return;
+        }
+        for session in sessions {
+            // Resolver traffic follows the lookups of every other client.
+            if !is_egress(session) || session.session.dst_port == 53 || self.is_allowed(session) {
+                continue;
+            }
+            if !self.seen.insert(session.uid.clone()) {
+                continue;
+            }
+            let destination = match session.dst_domain.as_deref() {
+                Some(d) if !UNRESOLVED_DOMAINS.contains(&d) && !d.is_empty() => d.to_string(),
+                _ => session.session.dst_ip.to_string(),
+            };
+            let process = session
+                .l7
+                .as_ref()
+                .map(|l7| l7.process_name.as_str())
+                .unwrap_or("unknown process");
+            let label = format!(
+                "{}:{}/{:?} by {}",
+                destination, session.session.dst_port, session.session.protocol, process
+            );
+            let series = self.series.entry(label.clone()).or_insert_with(|| Series {
+                label,
+                starts: Vec::new(),
+                last: session.clone(),
+            });
+            series.starts.push(session.stats.start_time);
+            series.last = session.clone();
+        }
+    }
+
+    /// Destinations newly found beaconing. Each is reported once.
+    pub fn detect(&mut self) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        for series in self.series.values_mut() {
+            if series.starts.len() < self.options.min_count || self.reported.contains(&series.label)
+            {
+                continue;
+            }
+            series.starts.sort();
+            let Some(timing) = periodicity(&series.starts) else {
+                continue;
+            };
+            let min_period = self.options.min_period.num_milliseconds() as f64 / 1000.0;
+            if timing.period < min_period || timing.jitter > self.options.max_jitter {
+                continue;
+            }
+            self.reported.insert(series.label.clone());
+            violations.push(PolicyViolation {
+                rule: "beaconing",
+                session: series.last.clone(),
+                detail: format!(
+                    "{} connections every {:.1}s, jitter {:.1}%",
+                    series.starts.len(),
+                    timing.period,
+                    timing.jitter * 100.0
+                ),
+                enforced: self.options.action == Action::Fail,
+            });
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starts(offsets: &[i64]) -> Vec<DateTime<Utc>> {
+        let origin = DateTime::parse_from_rfc3339("2025-07-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        offsets
+            .iter()
+            .map(|s| origin + Duration::seconds(*s))
+            .collect()
+    }
+
+    #[test]
+    fn regular_check_ins_are_periodic() {
+        let timing = periodicity(&starts(&[0, 60, 121, 180, 241, 300, 360])).unwrap();
+        assert_eq!(timing.period, 60.0);
+        assert!(timing.jitter < 0.05);
+
+        // A missed check-in does not hide the pattern.
+        let timing = periodicity(&starts(&[0, 60, 120, 240, 300, 360, 420])).unwrap();
+        assert_eq!(timing.period, 60.0);
+        assert_eq!(timing.jitter, 0.0);
+    }
+
+    #[test]
+    fn irregular_traffic_has_high_jitter() {
+        let timing = periodicity(&starts(&[0, 5, 95, 110, 300, 340, 600])).unwrap();
+        assert!(timing.jitter > BeaconOptions::default().max_jitter);
+        assert!(periodicity(&starts(&[0, 60])).is_none());
+        assert!(periodicity(&starts(&[0, 0, 0])).is_none());
+    }
+}
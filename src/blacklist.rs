@@ -0,0 +1,576 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use flodbadd::sessions::SessionInfo;
+use serde::Deserialize;
+
+use crate::report::Annotations;
+use crate::whitelist::ip_matches;
+
+/// Host names that hosts files map to themselves rather than block.
+const HOSTS_BUILTINS: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "0.0.0.0",
+];
+
+/// CSV header names recognised as the indicator column.
+const CSV_COLUMNS: &[&str] = &[
+    "indicator",
+    "ioc",
+    "value",
+    "domain",
+    "hostname",
+    "host",
+    "ip",
+    "ip_address",
+    "ip_range",
+    "cidr",
+    "url",
+];
+
+/// Hosts serving content for many unrelated users. A URL indicator on one of
+/// them blocks the whole host, not the single malicious path.
+const SHARED_HOSTS: &[&str] = &[
+    "github.com",
+    "raw.githubusercontent.com",
+    "gist.githubusercontent.com",
+    "objects.githubusercontent.com",
+    "codeload.github.com",
+    "gitlab.com",
+    "bitbucket.org",
+    "drive.google.com",
+    "docs.google.com",
+    "storage.googleapis.com",
+    "s3.amazonaws.com",
+    "dropbox.com",
+    "www.dropbox.com",
+    "dl.dropboxusercontent.com",
+    "onedrive.live.com",
+    "1drv.ms",
+    "cdn.discordapp.com",
+    "pastebin.com",
+    "transfer.sh",
+    "mega.nz",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndicatorKind {
+    /// Matches the domain and its subdomains.
+    Domain,
+    /// The host of a URL indicator. Matches that host only, since feeds list
+    /// URLs on shared hosts whose other subdomains are unrelated.
+    Host,
+    /// An IP address or CIDR range.
+    Network,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Indicator {
+    pub value: String,
+    pub kind: IndicatorKind,
+    /// Name of the list the indicator came from, reported with each hit.
+    pub list: String,
+    /// Threat-intel record id (STIX indicator id, MISP attribute uuid).
+    pub id: Option<String>,
+    /// Threat actors, intrusion sets or malware the record is linked to.
+    pub labels: Vec<String>,
+}
+
+impl Indicator {
+    /// A URL indicator whose host is shared by many users.
+    pub fn is_shared_host(&self) -> bool {
+        self.kind == IndicatorKind::Host && SHARED_HOSTS.contains(&self.value.as_str())
+    }
+}
+
+/// A session matching a custom blacklist.
+pub struct Hit {
+    pub session: SessionInfo,
+    pub indicator: Indicator,
+}
+
+/// Flodbadd's blacklist file format.
+#[derive(Deserialize)]
+struct BlacklistsJSON {
+    blacklists: Vec<BlacklistInfo>,
+}
+
+#[derive(Deserialize)]
+struct BlacklistInfo {
+    name: String,
+    #[serde(default)]
+    ip_ranges: Vec<String>,
+}
+
+/// Blocklists loaded with `--custom-blacklist`. IP ranges are handed to the
+/// engine (see [`CustomBlacklist::engine_json`]); domains and URL hosts,
+/// which the engine does not match, are checked here.
+#[derive(Debug, Default, Clone)]
+pub struct CustomBlacklist {
+    pub indicators: Vec<Indicator>,
+}
+
+/// Normalizes a blocklist value to a domain or IP/CIDR: URLs are reduced to
+/// their host (matched exactly), ports and trailing dots are dropped.
+/// Returns `None` for anything else.
+pub fn parse_indicator(raw: &str, list: &str) -> Option<Indicator> {
+    let mut value = raw.trim().trim_matches('"').trim();
+    let mut is_url = false;
+    if let Some((_, rest)) = value.split_once("://") {
+        value = rest;
+        is_url = true;
+    }
+    if value.contains("://") || value.is_empty() {
+        return None;
+    }
+    let host_end = value.find(['/', '?']).unwrap_or(value.len());
+    let is_cidr = value[host_end..]
+        .strip_prefix('/')
+        .map(|prefix| prefix.parse::<u8>().is_ok())
+        .unwrap_or(false);
+    if is_cidr && value[..host_end].parse::<IpAddr>().is_ok() {
+        return Some(Indicator {
+            value: value.to_string(),
+            kind: IndicatorKind::Network,
+            list: list.to_string(),
+            id: None,
+            labels: Vec::new(),
+        });
+    }
+    is_url |= host_end < value.len();
+    let host = &value[..host_end];
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.split_once(']'))
+        .map(|(h, _)| h)
+        .unwrap_or(host);
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Some(Indicator {
+            value: ip.to_string(),
+            kind: IndicatorKind::Network,
+            list: list.to_string(),
+            id: None,
+            labels: Vec::new(),
+        });
+    }
+    let host = host.split(':').next().unwrap_or(host);
+    let host = host
+        .trim_start_matches("*.")
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+    let valid = host.contains('.')
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    valid.then(|| Indicator {
+        value: host,
+        kind: if is_url {
+            IndicatorKind::Host
+        } else {
+            IndicatorKind::Domain
+        },
+        list: list.to_string(),
+        id: None,
+        labels: Vec::new(),
+    })
+}
+
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+impl CustomBlacklist {
+    /// Loads each file, picking the format from its content and extension:
+    /// Flodbadd JSON, STIX 2.1 bundles or MISP exports (`.json`), CSV
+    /// (`.csv`), otherwise one indicator per line in plain or hosts-file
+    /// syntax.
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let mut blacklist = CustomBlacklist::default();
+        for path in paths {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read blacklist '{}'", path))?;
+            let list = Path::new(path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.clone());
+            let lower = path.to_ascii_lowercase();
+            let before = blacklist.indicators.len();
+            if lower.ends_with(".json") {
+                blacklist
+                    .add_json(&text, &list)
+                    .with_context(|| format!("Failed to parse blacklist '{}'", path))?;
+            } else if lower.ends_with(".csv") {
+                blacklist.add_csv(&text, &list);
+            } else {
+                blacklist.add_lines(&text, &list);
+            }
+            println!(
+                "Loaded {} indicators from custom blacklist {}",
+                blacklist.indicators.len() - before,
+                path
+            );
+            let shared: Vec<&str> = blacklist.indicators[before..]
+                .iter()
+                .filter(|i| i.is_shared_host())
+                .map(|i| i.value.as_str())
+                .collect();
+            if !shared.is_empty() {
+                eprintln!(
+                    "Warning: {} URL indicators in {} are on shared hosts and block every session to them: {}",
+                    shared.len(),
+                    path,
+                    shared.join(", ")
+                );
+            }
+        }
+        Ok(blacklist)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indicators.is_empty()
+    }
+
+    /// The IP ranges in Flodbadd's blacklist format, one blacklist per list,
+    /// for `FlodbaddCapture::set_custom_blacklists`. `None` without ranges.
+    pub fn engine_json(&self) -> Option<String> {
+        let mut lists: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for indicator in &self.indicators {
+            if indicator.kind == IndicatorKind::Network {
+                lists
+                    .entry(indicator.list.as_str())
+                    .or_default()
+                    .push(indicator.value.as_str());
+            }
+        }
+        if lists.is_empty() {
+            return None;
+        }
+        let blacklists: Vec<serde_json::Value> = lists
+            .into_iter()
+            .map(|(name, ranges)| serde_json::json!({ "name": name, "ip_ranges": ranges }))
+            .collect();
+        Some(
+            serde_json::json!({
+                "date": Utc::now().format("%B %-d %Y").to_string(),
+                "signature": null,
+                "blacklists": blacklists,
+            })
+            .to_string(),
+        )
+    }
+
+    /// Adds the indicators of a STIX bundle or MISP export, or the IP ranges
+    /// of a Flodbadd blacklist file (one list per blacklist name).
+    pub fn add_json(&mut self, text: &str, list: &str) -> Result<()> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+        if let Some(indicators) = crate::intel::parse(&value, list) {
+            self.indicators.extend(indicators);
+            return Ok(());
+        }
+        let parsed: BlacklistsJSON = serde_json::from_value(value)?;
+        for info in parsed.blacklists {
+            self.indicators.extend(
+                info.ip_ranges
+                    .iter()
+                    .filter_map(|range| parse_indicator(range, &info.name)),
+            );
+        }
+        Ok(())
+    }
+
+    /// Adds plain domain/IP/CIDR lists and hosts files (`0.0.0.0 evil.com`).
+    /// `#` starts a comment.
+    pub fn add_lines(&mut self, text: &str, list: &str) {
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut tokens = line.split_whitespace();
+            let Some(first) = tokens.next() else {
+                continue;
+            };
+            let rest: Vec<&str> = tokens.collect();
+            if rest.is_empty() {
+                self.indicators.extend(parse_indicator(first, list));
+            } else if first.parse::<IpAddr>().is_ok() {
+                // Hosts-file syntax: the address is a sink, the names are
+                // the blocked hosts.
+                self.indicators.extend(
+                    rest.iter()
+                        .filter(|host| !HOSTS_BUILTINS.contains(host))
+                        .filter_map(|host| parse_indicator(host, list)),
+                );
+            }
+        }
+    }
+
+    /// Adds the indicator column of a CSV file: the first column named like
+    /// an indicator (`indicator`, `domain`, `ip`, `url`, ...) or, without a
+    /// recognised header, the first column.
+    pub fn add_csv(&mut self, text: &str, list: &str) {
+        let mut lines = text
+            .lines()
+            .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+            .peekable();
+        let mut column = 0;
+        if let Some(header) = lines.peek() {
+            let names = split_csv_line(header);
+            if let Some(index) = names
+                .iter()
+                .position(|name| CSV_COLUMNS.contains(&name.trim().to_ascii_lowercase().as_str()))
+            {
+                column = index;
+                lines.next();
+            }
+        }
+        for line in lines {
+            if let Some(value) = split_csv_line(line).get(column) {
+                self.indicators.extend(parse_indicator(value, list));
+            }
+        }
+    }
+
+    /// The first indicator of one of `kinds` matching a destination, by
+    /// domain (including subdomains), URL host (exact) or IP.
+    fn match_destination(
+        &self,
+        domain: Option<&str>,
+        ip: &IpAddr,
+        kinds: impl Fn(IndicatorKind) -> bool,
+    ) -> Option<&Indicator> {
+        let domain = domain.map(|d| d.trim_end_matches('.').to_ascii_lowercase());
+        self.indicators
+            .iter()
+            .filter(|indicator| kinds(indicator.kind))
+            .find(|indicator| match indicator.kind {
+                IndicatorKind::Network => ip_matches(&indicator.value, ip),
+                IndicatorKind::Host => domain.as_deref() == Some(indicator.value.as_str()),
+                IndicatorKind::Domain => domain.as_deref().is_some_and(|d| {
+                    d == indicator.value
+                        || d.strip_suffix(indicator.value.as_str())
+                            .is_some_and(|prefix| prefix.ends_with('.'))
+                }),
+            })
+    }
+
+    /// Sessions matching a domain or URL indicator. IP ranges are matched by
+    /// the engine and reported among its blacklisted sessions.
+    pub fn check(&self, sessions: &[SessionInfo]) -> Vec<Hit> {
+        self.hits(sessions, |kind| kind != IndicatorKind::Network)
+    }
+
+    /// The indicators behind the engine's hits on the custom IP ranges, so
+    /// that they are reported with their list and intel record.
+    pub fn range_hits(&self, blacklisted: &[SessionInfo]) -> Vec<Hit> {
+        self.hits(blacklisted, |kind| kind == IndicatorKind::Network)
+    }
+
+    fn hits(&self, sessions: &[SessionInfo], kinds: impl Fn(IndicatorKind) -> bool) -> Vec<Hit> {
+        sessions
+            .iter()
+            .filter_map(|session| {
+                self.match_destination(
+                    session.dst_domain.as_deref(),
+                    &session.session.dst_ip,
+                    &kinds,
+                )
+                .map(|indicator| Hit {
+                    session: session.clone(),
+                    indicator: indicator.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Adds each hit to the JSON report as a `blacklist` field, so alerts link
+/// back to the intel record.
+pub fn annotate(hits: &[Hit], annotations: &mut Annotations) -> Result<()> {
+    for hit in hits {
+        annotations.add(
+            &hit.session,
+            "blacklist",
+            &serde_json::json!({
+                "list": hit.indicator.list,
+                "indicator": hit.indicator.value,
+                "id": hit.indicator.id,
+                "labels": hit.indicator.labels,
+            }),
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints one line per hit with the list that flagged it and, for threat
+/// intel, the record id and linked actors.
+pub fn print_hits(hits: &[Hit]) {
+    for hit in hits {
+        let s = &hit.session;
+        let mut intel = String::new();
+        if let Some(id) = &hit.indicator.id {
+            intel.push_str(&format!(" [id: {}]", id));
+        }
+        if !hit.indicator.labels.is_empty() {
+            intel.push_str(&format!(" [labels: {}]", hit.indicator.labels.join(", ")));
+        }
+        println!(
+            "  - {} -> {}:{} ({}) matched {} (list: {}){}",
+            s.session.src_ip,
+            s.dst_domain
+                .clone()
+                .unwrap_or_else(|| s.session.dst_ip.to_string()),
+            s.session.dst_port,
+            s.l7.as_ref()
+                .map(|l7| l7.process_name.as_str())
+                .unwrap_or("unknown process"),
+            hit.indicator.value,
+            hit.indicator.list,
+            intel
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(blacklist: &CustomBlacklist) -> Vec<&str> {
+        blacklist
+            .indicators
+            .iter()
+            .map(|i| i.value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn plain_and_hosts_lists() {
+        let mut blacklist = CustomBlacklist::default();
+        blacklist.add_lines(
+            "# IOC drop 2025-07-01\nevil.example.com\n203.0.113.0/24  # C2 range\n\n\
+             0.0.0.0 tracker.example.net ads.example.org\n127.0.0.1 localhost\n2001:db8::1\n",
+            "iocs",
+        );
+        assert_eq!(
+            values(&blacklist),
+            vec![
+                "evil.example.com",
+                "203.0.113.0/24",
+                "tracker.example.net",
+                "ads.example.org",
+                "2001:db8::1"
+            ]
+        );
+        assert!(blacklist.indicators.iter().all(|i| i.list == "iocs"));
+    }
+
+    #[test]
+    fn csv_and_json_lists() {
+        let mut blacklist = CustomBlacklist::default();
+        blacklist.add_csv(
+            "id,dateadded,url,threat\n1,2025-07-01,\"http://bad.example.com:8080/payload.sh\",malware\n2,2025-07-01,http://198.51.100.7/x,malware\n",
+            "urlhaus",
+        );
+        blacklist
+            .add_json(r#"{"date":"d","signature":null,"blacklists":[{"name":"firehol_level1","ip_ranges":["192.0.2.0/24"]}]}"#, "ignored")
+            .unwrap();
+        assert_eq!(
+            values(&blacklist),
+            vec!["bad.example.com", "198.51.100.7", "192.0.2.0/24"]
+        );
+        assert_eq!(blacklist.indicators[2].list, "firehol_level1");
+    }
+
+    #[test]
+    fn destinations_match_domains_and_networks() {
+        let mut blacklist = CustomBlacklist::default();
+        blacklist.add_lines("evil.example.com\n203.0.113.0/24\n", "iocs");
+        let other: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(blacklist
+            .match_destination(Some("cdn.evil.example.com."), &other, |_| true)
+            .is_some());
+        assert!(blacklist
+            .match_destination(Some("notevil.example.com"), &other, |_| true)
+            .is_none());
+        let hit = blacklist
+            .match_destination(None, &"203.0.113.9".parse().unwrap(), |_| true)
+            .unwrap();
+        assert_eq!(hit.value, "203.0.113.0/24");
+    }
+
+    #[test]
+    fn ranges_go_to_the_engine() {
+        let mut blacklist = CustomBlacklist::default();
+        assert_eq!(blacklist.engine_json(), None);
+        blacklist.add_lines(
+            "evil.example.com
+203.0.113.0/24
+2001:db8::1
+",
+            "iocs",
+        );
+        blacklist.add_lines(
+            "198.51.100.7
+",
+            "c2",
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&blacklist.engine_json().unwrap()).unwrap();
+        assert_eq!(
+            json["blacklists"],
+            serde_json::json!([
+                {"name": "c2", "ip_ranges": ["198.51.100.7"]},
+                {"name": "iocs", "ip_ranges": ["203.0.113.0/24", "2001:db8::1"]},
+            ])
+        );
+    }
+
+    #[test]
+    fn url_indicators_match_their_host_only() {
+        let mut blacklist = CustomBlacklist::default();
+        blacklist.add_csv(
+            "url\nhttps://raw.githubusercontent.com/evil/repo/main/x.sh\ngithub.com/evil/repo\n",
+            "urlhaus",
+        );
+        let kinds: Vec<IndicatorKind> = blacklist.indicators.iter().map(|i| i.kind).collect();
+        assert_eq!(kinds, vec![IndicatorKind::Host, IndicatorKind::Host]);
+        assert!(blacklist.indicators.iter().all(|i| i.is_shared_host()));
+        let ip: IpAddr = "185.199.108.133".parse().unwrap();
+        assert!(blacklist
+            .match_destination(Some("raw.githubusercontent.com"), &ip, |_| true)
+            .is_some());
+        assert!(blacklist
+            .match_destination(Some("objects.githubusercontent.com"), &ip, |_| true)
+            .is_none());
+        assert!(blacklist
+            .match_destination(Some("api.github.com"), &ip, |_| true)
+            .is_none());
+
+        let indicator = parse_indicator("http://bad.example.com:8080/payload.sh", "u").unwrap();
+        assert_eq!(indicator.kind, IndicatorKind::Host);
+        assert!(!indicator.is_shared_host());
+        assert_eq!(
+            parse_indicator("evil.example.com", "u").unwrap().kind,
+            IndicatorKind::Domain
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::policy::{validate_network, Action};
+use crate::whitelist::ip_matches;
+
+/// Category database compiled into the binary. Files given with
+/// `--category-db` use the same format and extend it.
+const BUILTIN: &str = include_str!("../categories/categories.json");
+
+/// A class of services that builds rarely have a reason to reach.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Category {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Domains, each matching itself and its subdomains.
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Addresses or CIDR ranges.
+    #[serde(default)]
+    pub ips: Vec<String>,
+    /// Destination ports, e.g. mining stratum ports.
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CategoryFile {
+    #[serde(default)]
+    version: String,
+    categories: Vec<Category>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CategoryDatabase {
+    pub version: String,
+    pub categories: Vec<Category>,
+}
+
+/// Why a destination belongs to a category.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CategoryMatch {
+    pub category: String,
+    /// The domain, network or port that matched.
+    pub entry: String,
+}
+
+/// Whether `domain` is `entry` or one of its subdomains.
+fn domain_within(domain: &str, entry: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let entry = entry.to_ascii_lowercase();
+    domain == entry
+        || (domain.len() > entry.len()
+            && domain.ends_with(&entry)
+            && domain.as_bytes()[domain.len() - entry.len() - 1] == b'.')
+}
+
+impl CategoryDatabase {
+    fn parse(text: &str) -> Result<CategoryFile> {
+        let file: CategoryFile = serde_json::from_str(text)?;
+        for category in &file.categories {
+            for ip in &category.ips {
+                validate_network(ip).with_context(|| format!("In category '{}'", category.name))?;
+            }
+        }
+        Ok(file)
+    }
+
+    pub fn builtin() -> Self {
+        let file = Self::parse(BUILTIN).expect("bundled category database is valid");
+        CategoryDatabase {
+            version: file.version,
+            categories: file.categories,
+        }
+    }
+
+    /// The bundled database extended with `paths`. Entries of a category
+    /// already known are added to it; new categories are appended.
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let mut database = Self::builtin();
+        for path in paths {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read category database '{}'", path))?;
+            let file = Self::parse(&text)
+                .with_context(|| format!("Failed to parse category database '{}'", path))?;
+            println!(
+                "Extended category database {} with {} ({} categories)",
+                database.version,
+                path,
+                file.categories.len()
+            );
+            database.merge(file.categories);
+        }
+        Ok(database)
+    }
+
+    fn merge(&mut self, categories: Vec<Category>) {
+        for category in categories {
+            match self.categories.iter_mut().find(|c| c.name == category.name) {
+                Some(existing) => {
+                    existing.domains.extend(category.domains);
+                    existing.ips.extend(category.ips);
+                    existing.ports.extend(category.ports);
+                    if !category.description.is_empty() {
+                        existing.description = category.description;
+                    }
+                }
+                None => self.categories.push(category),
+            }
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.categories.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    /// Categories a destination belongs to. Domains are checked first, then
+    /// addresses, then ports.
+    pub fn classify(&self, domain: Option<&str>, ip: &IpAddr, port: u16) -> Vec<CategoryMatch> {
+        let mut matches = Vec::new();
+        for category in &self.categories {
+            let entry = domain
+                .and_then(|d| category.domains.iter().find(|e| domain_within(d, e)))
+                .or_else(|| category.ips.iter().find(|network| ip_matches(network, ip)))
+                .cloned()
+                .or_else(|| {
+                    category
+                        .ports
+                        .contains(&port)
+                        .then(|| format!("port {}", port))
+                });
+            if let Some(entry) = entry {
+                matches.push(CategoryMatch {
+                    category: category.name.clone(),
+                    entry,
+                });
+            }
+        }
+        matches
+    }
+}
+
+impl Default for CategoryDatabase {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+/// A `--category-allow <TARGET>=<PROCESS>` rule: a process allowed to reach
+/// a category, or one domain or network within it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CategoryAllow {
+    /// Category name, domain (with its subdomains) or IP/CIDR.
+    pub target: String,
+    /// Process name or path, or `*` for any process.
+    pub process: String,
+}
+
+impl CategoryAllow {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.split_once('=') {
+            Some((target, process)) if !target.is_empty() && !process.is_empty() => {
+                Ok(CategoryAllow {
+                    target: target.to_string(),
+                    process: process.to_string(),
+                })
+            }
+            _ => bail!(
+                "Invalid category allow rule '{}' (use <category|domain|cidr>=<process|*>)",
+                value
+            ),
+        }
+    }
+
+    fn allows(
+        &self,
+        found: &CategoryMatch,
+        domain: Option<&str>,
+        ip: &IpAddr,
+        process: Option<(&str, &str)>,
+    ) -> bool {
+        let target = self.target == found.category
+            || domain.is_some_and(|d| domain_within(d, &self.target))
+            || ip_matches(&self.target, ip);
+        let process = self.process == "*"
+            || process.is_some_and(|(name, path)| self.process == name || self.process == path);
+        target && process
+    }
+}
+
+/// Per-category actions and exemptions.
+#[derive(Debug, Clone)]
+pub struct CategoryOptions {
+    pub database: CategoryDatabase,
+    /// Action per category; categories not listed use `default_action`.
+    pub actions: BTreeMap<String, Action>,
+    pub default_action: Action,
+    pub allow: Vec<CategoryAllow>,
+}
+
+impl Default for CategoryOptions {
+    fn default() -> Self {
+        CategoryOptions {
+            database: CategoryDatabase::builtin(),
+            actions: BTreeMap::new(),
+            default_action: Action::Warn,
+            allow: Vec::new(),
+        }
+    }
+}
+
+impl CategoryOptions {
+    /// Parses a `--category <NAME>=<ACTION>` setting; `all` sets the default.
+    pub fn set(&mut self, value: &str) -> Result<()> {
+        let Some((name, action)) = value.split_once('=') else {
+            bail!(
+                "Invalid category setting '{}' (use <name>=<off|warn|fail>)",
+                value
+            );
+        };
+        let action = Action::parse(action)?;
+        if name == "all" {
+            self.default_action = action;
+        } else if self.database.names().contains(&name) {
+            self.actions.insert(name.to_string(), action);
+        } else {
+            bail!(
+                "Unknown category '{}' (available: {}, all)",
+                name,
+                self.database.names().join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    fn action(&self, category: &str) -> Action {
+        self.actions
+            .get(category)
+            .copied()
+            .unwrap_or(self.default_action)
+    }
+
+    /// The categories of a destination that are not off or allowed for the
+    /// process, with their action.
+    pub fn check(
+        &self,
+        domain: Option<&str>,
+        ip: &IpAddr,
+        port: u16,
+        process: Option<(&str, &str)>,
+    ) -> Vec<(CategoryMatch, Action)> {
+        self.database
+            .classify(domain, ip, port)
+            .into_iter()
+            .map(|found| {
+                let action = self.action(&found.category);
+                (found, action)
+            })
+            .filter(|(found, action)| {
+                *action != Action::Off
+                    && !self
+                        .allow
+                        .iter()
+                        .any(|a| a.allows(found, domain, ip, process))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_categories() {
+        let database = CategoryDatabase::builtin();
+        assert_eq!(database.names(), vec!["paste", "tunnel", "tor", "mining"]);
+        let ip: IpAddr = "203.0.113.5".parse().unwrap();
+        let found = database.classify(Some("gist.githubusercontent.com"), &ip, 443);
+        assert_eq!(
+            found,
+            vec![CategoryMatch {
+                category: "paste".to_string(),
+                entry: "gist.githubusercontent.com".to_string()
+            }]
+        );
+        assert_eq!(
+            database.classify(Some("abc123.ngrok-free.app"), &ip, 443)[0].category,
+            "tunnel"
+        );
+        assert_eq!(database.classify(None, &ip, 3333)[0].entry, "port 3333");
+        // Ports shared with unrelated services are not listed.
+        for port in [4444, 5555, 7777, 9001] {
+            assert!(database.classify(None, &ip, port).is_empty(), "{}", port);
+        }
+        assert_eq!(
+            database.classify(None, &"171.25.193.9".parse().unwrap(), 443)[0].category,
+            "tor"
+        );
+        assert!(database.classify(Some("notngrok.io"), &ip, 443).is_empty());
+    }
+
+    #[test]
+    fn local_database_extends_the_bundled_one() {
+        let mut database = CategoryDatabase::builtin();
+        let file = CategoryDatabase::parse(
+            r#"{"categories":[{"name":"tor","ips":["198.51.100.0/24"]},
+                {"name":"webhook","description":"Request catchers","domains":["webhook.site"]}]}"#,
+        )
+        .unwrap();
+        database.merge(file.categories);
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+        assert_eq!(database.classify(None, &ip, 443)[0].category, "tor");
+        assert_eq!(database.classify(Some("webhook.site"), &ip, 443).len(), 2);
+        assert!(
+            CategoryDatabase::parse(r#"{"categories":[{"name":"x","ips":["nope"]}]}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn actions_and_process_rules() {
+        let mut options = CategoryOptions::default();
+        options.set("mining=fail").unwrap();
+        options.set("tor=off").unwrap();
+        assert!(options.set("warez=fail").is_err());
+        options.allow = vec![
+            CategoryAllow::parse("gist.githubusercontent.com=git").unwrap(),
+            CategoryAllow::parse("tunnel=/usr/local/bin/cloudflared").unwrap(),
+        ];
+        let ip: IpAddr = "203.0.113.5".parse().unwrap();
+        let git = Some(("git", "/usr/bin/git"));
+        let curl = Some(("curl", "/usr/bin/curl"));
+
+        assert!(options
+            .check(Some("gist.githubusercontent.com"), &ip, 443, git)
+            .is_empty());
+        let flagged = options.check(Some("gist.githubusercontent.com"), &ip, 443, curl);
+        assert_eq!(flagged[0].1, Action::Warn);
+        assert!(options
+            .check(
+                Some("x.trycloudflare.com"),
+                &ip,
+                443,
+                Some(("cloudflared", "/usr/local/bin/cloudflared"))
+            )
+            .is_empty());
+        assert_eq!(options.check(None, &ip, 14444, curl)[0].1, Action::Fail);
+        assert!(options.check(None, &ip, 9001, curl).is_empty());
+        assert!(CategoryAllow::parse("paste").is_err());
+    }
+}
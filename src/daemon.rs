@@ -1,6 +1,8 @@
-use std::time::Duration;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
+use chrono::Utc;
 use flodbadd::{
     analyzer::SessionAnalyzer,
     capture::FlodbaddCapture,
@@ -10,47 +12,138 @@ use flodbadd::{
 use tokio::time::interval;
 use tracing::info;
 
-/// Run the monitoring loop.  This never returns unless an error occurs or a
-/// policy violation is detected.
+use crate::anomaly::{print_explanations, AnomalyOptions};
+use crate::beacon::{BeaconDetector, BeaconOptions};
+use crate::blacklist::{print_hits, CustomBlacklist};
+use crate::dns::DomainDetector;
+use crate::ingress::ListenerWatch;
+use crate::lineage::{self, CmdlineMode, LineageRecorder};
+use crate::policy::{report, PolicyOptions};
+use crate::volume;
+use crate::whitelist::WhitelistSource;
+
+/// How often `watch` writes the anomaly model out. It is also written before
+/// the pipeline is cancelled and when `watch` is stopped.
+const MODEL_SAVE_EVERY: Duration = Duration::from_secs(300);
+
+/// What `watch` checks and how it reacts to a violation.
+pub struct WatchOptions {
+    pub check_whitelist: bool,
+    pub check_blacklist: bool,
+    pub check_anomaly: bool,
+    /// Cancel the CI pipeline and exit 1 when a violation is found.
+    pub cancel_on_violation: bool,
+    pub whitelist_source: WhitelistSource,
+    pub custom_blacklist: CustomBlacklist,
+    pub anomaly_options: AnomalyOptions,
+    pub policy_options: PolicyOptions,
+    pub beacon_options: BeaconOptions,
+    pub cmdline: CmdlineMode,
+}
+
+/// Run the monitoring loop.  This only returns when stopped by Ctrl-C or
+/// SIGTERM, or when an error occurs or a policy violation is detected.
 ///
 /// * `poll_every` – number of seconds between anomaly checks.
-/// * `fail_on_violation` – whether to cancel the CI pipeline and exit 1 when a violation is found.
+/// * `options` – the checks to run and whether to cancel the CI pipeline.
 ///
-pub async fn watch_daemon(
-    poll_every: u64,
-    check_whitelist: bool,
-    check_blacklist: bool,
-    check_anomaly: bool,
-    cancel_on_violation: bool,
-    custom_wl: Option<&str>,
-) -> Result<()> {
+pub async fn watch_daemon(poll_every: u64, options: WatchOptions) -> Result<()> {
+    let WatchOptions {
+        check_whitelist,
+        check_blacklist,
+        check_anomaly,
+        cancel_on_violation,
+        whitelist_source,
+        custom_blacklist,
+        anomaly_options,
+        policy_options,
+        beacon_options,
+        cmdline,
+    } = options;
     let interfaces = get_valid_network_interfaces();
     let capture = FlodbaddCapture::new();
-    if let Some(path) = custom_wl {
-        if std::path::Path::new(path).exists() {
-            let json = std::fs::read_to_string(path)?;
-            capture.set_custom_whitelists(&json).await;
-        } else {
-            eprintln!("Custom whitelist file '{}' not found, ignoring", path);
-        }
+    let mut next_expiry = None;
+    let mut budgeted = Vec::new();
+    if let Some(enforced) = whitelist_source.load()? {
+        enforced.print_expired();
+        capture.set_custom_whitelists(&enforced.json).await;
+        next_expiry = enforced.next_expiry;
+        budgeted = enforced.budgeted;
     }
-    capture.start(&interfaces).await?;
+    if let Some(json) = custom_blacklist.engine_json() {
+        capture.set_custom_blacklists(&json).await?;
+    }
+    // Volume violations already reported, so each is printed once per run
+    let mut reported_volume: HashSet<String> = HashSet::new();
+    // Sessions whose destination was still resolving, checked for direct-IP
+    // egress again once they have a name
+    let mut awaiting_resolution: HashSet<String> = HashSet::new();
+    // Anomalies already explained; the analyzer keeps returning them
+    let mut explained: HashSet<String> = HashSet::new();
+    let mut beacons = BeaconDetector::new(beacon_options);
+    let mut domains = DomainDetector::new(policy_options.domains.clone());
+    let mut lineage = LineageRecorder::new(cmdline);
 
-    // Initialize analyzer for anomaly detection
+    // Initialize analyzer for anomaly detection, replaying the saved model
     let analyzer = SessionAnalyzer::new();
     analyzer.start().await;
+    let mut model = anomaly_options.warm_analyzer(&analyzer).await?;
+    let mut model_saved_at = Instant::now();
+    let mut model_changed = false;
+
+    // Listening sockets open before the run are not reported
+    let mut listeners = ListenerWatch::new(&policy_options.ingress);
+
+    let run_start = Utc::now();
+    capture.start(&interfaces).await?;
 
     info!("Flodviddar daemon started – polling every {poll_every}s");
     let mut ticker = interval(Duration::from_secs(poll_every));
 
+    #[cfg(not(target_os = "windows"))]
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
     loop {
-        ticker.tick().await; // wait for next tick
+        // Wait for the next tick, or stop on Ctrl-C/SIGTERM
+        #[cfg(not(target_os = "windows"))]
+        let stop = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = tokio::signal::ctrl_c() => true,
+            _ = sigterm.recv() => true,
+        };
+        #[cfg(target_os = "windows")]
+        let stop = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = tokio::signal::ctrl_c() => true,
+        };
+        if stop {
+            info!("Signal received, stopping");
+            if model_changed {
+                anomaly_options.save_model(&model)?;
+            }
+            return Ok(());
+        }
+
+        // Reload the whitelist once an entry or exception expires so it
+        // stops matching
+        if next_expiry.is_some_and(|at| at <= Utc::now()) {
+            next_expiry = None;
+            budgeted = Vec::new();
+            if let Some(enforced) = whitelist_source.load()? {
+                enforced.print_expired();
+                capture.set_custom_whitelists(&enforced.json).await;
+                next_expiry = enforced.next_expiry;
+                budgeted = enforced.budgeted;
+            }
+        }
 
         let mut violations = false;
         let mut violating_sessions: Vec<SessionInfo> = Vec::new();
 
         // Update analyzer with new sessions
         let mut new_sessions = capture.get_sessions(true).await;
+        // Sessions still open at the tick have a live process to read
+        lineage.record(&capture.get_current_sessions(false).await);
         if !new_sessions.is_empty() {
             analyzer.analyze_sessions(&mut new_sessions).await;
         }
@@ -69,25 +162,128 @@ pub async fn watch_daemon(
             let blacklisted = capture.get_blacklisted_sessions(false).await;
             if !blacklisted.is_empty() {
                 info!("Blacklisted sessions detected: {}", blacklisted.len());
+                print_hits(&custom_blacklist.range_hits(&blacklisted));
                 violations = true;
                 violating_sessions.extend(blacklisted.clone());
             }
+            let hits = custom_blacklist.check(&new_sessions);
+            if !hits.is_empty() {
+                info!("Custom blacklist hits detected: {}", hits.len());
+                print_hits(&hits);
+                violations = true;
+                violating_sessions.extend(hits.into_iter().map(|hit| hit.session));
+            }
+        }
+
+        let mut policy_violations = policy_options.check(&new_sessions);
+        for session in &new_sessions {
+            if policy_options.awaits_resolution(session) {
+                awaiting_resolution.insert(session.uid.clone());
+            } else {
+                awaiting_resolution.remove(&session.uid);
+            }
+        }
+        if !awaiting_resolution.is_empty() {
+            let mut still_awaiting = HashSet::new();
+            for session in capture.get_sessions(false).await {
+                if !awaiting_resolution.contains(&session.uid) {
+                    continue;
+                }
+                if policy_options.awaits_resolution(&session) {
+                    still_awaiting.insert(session.uid.clone());
+                } else {
+                    policy_violations.extend(policy_options.direct_ip_violation(&session));
+                }
+            }
+            awaiting_resolution = still_awaiting;
+        }
+        let policy_violations = report(policy_violations);
+        if !policy_violations.is_empty() {
+            violations = true;
+            violating_sessions.extend(policy_violations);
+        }
+
+        // Query volume per registrable domain adds up over the run
+        domains.observe(&new_sessions);
+        let domain_findings = report(domains.detect());
+        if !domain_findings.is_empty() {
+            violations = true;
+            violating_sessions.extend(domain_findings);
+        }
+
+        if listeners.check()? {
+            violations = true;
+        }
+
+        // Beaconing is judged over the session history of the whole run
+        beacons.observe(&new_sessions);
+        let beaconing = report(beacons.detect());
+        if !beaconing.is_empty() {
+            violations = true;
+            violating_sessions.extend(beaconing);
+        }
+
+        // Volumes are totals over the whole run, not over the new sessions
+        if !policy_options.volume.is_empty() || !budgeted.is_empty() {
+            let all_sessions = capture.get_sessions(false).await;
+            let new_violations: Vec<volume::VolumeViolation> = policy_options
+                .volume
+                .check(&all_sessions, &budgeted)
+                .into_iter()
+                .filter(|v| reported_volume.insert(v.key()))
+                .collect();
+            let volume_violations = volume::report(new_violations);
+            if !volume_violations.is_empty() {
+                violations = true;
+                violating_sessions.extend(volume_violations);
+            }
+        }
+
+        let anomalies = anomaly_options.classify(
+            &model,
+            &new_sessions,
+            analyzer.get_anomalous_sessions().await,
+            run_start,
+        );
+        if !new_sessions.is_empty() {
+            anomaly_options.learn(&mut model, &new_sessions, &anomalies);
+            model_changed = true;
+        }
+        if model_changed && model_saved_at.elapsed() >= MODEL_SAVE_EVERY {
+            anomaly_options.save_model(&model)?;
+            model_saved_at = Instant::now();
+            model_changed = false;
         }
 
         if check_anomaly {
-            let anomalous = analyzer.get_anomalous_sessions().await;
-            if !anomalous.is_empty() {
-                info!("Anomalous sessions detected: {}", anomalous.len());
+            let unexplained = anomalies.unexplained(&mut explained);
+            if !unexplained.recorded.is_empty() {
+                info!(
+                    "Anomalous sessions recorded (warm-up or below threshold, not enforced): {}",
+                    unexplained.recorded.len()
+                );
+            }
+            print_explanations(
+                &anomaly_options,
+                &unexplained,
+                &model.baseline(&new_sessions, &anomalies),
+            );
+            if !anomalies.enforced.is_empty() {
+                info!("Anomalous sessions detected: {}", anomalies.enforced.len());
                 violations = true;
-                violating_sessions.extend(anomalous.clone());
+                violating_sessions.extend(anomalies.enforced);
             }
         }
 
         if cancel_on_violation && violations {
+            if model_changed {
+                anomaly_options.save_model(&model)?;
+            }
             println!("\n=== Violating Sessions ===");
             for line in format_sessions_log(&violating_sessions) {
                 println!("{}", line);
             }
+            lineage::print(&lineage, &violating_sessions);
             println!("Policy violations detected. Cancelling CI pipeline...");
             let _ = super::halt_ci_pipeline("Flodviddar daemon detected violations");
             std::process::exit(1);
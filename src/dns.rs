@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use flodbadd::sessions::SessionInfo;
+
+use crate::explain::looks_like_reverse_dns;
+use crate::generalize::PublicSuffixList;
+use crate::policy::{is_egress, Action, PolicyViolation, UNRESOLVED_DOMAINS};
+use crate::whitelist::domain_matches;
+
+/// Registrable labels shorter than this are too short to judge.
+const DGA_MIN_LENGTH: usize = 8;
+
+/// Subdomain parts shorter than this are not judged by their entropy.
+const ENTROPY_MIN_LENGTH: usize = 24;
+
+/// Thresholds of the DNS tunneling and DGA heuristics.
+#[derive(Debug, Clone)]
+pub struct DomainOptions {
+    pub action: Action,
+    /// Longest subdomain label considered normal.
+    pub max_label_length: usize,
+    /// Longest full name considered normal.
+    pub max_name_length: usize,
+    /// Highest Shannon entropy, in bits per character, of the subdomain
+    /// part considered normal. Hex payloads are scaled to the alphabet of
+    /// letters and digits first (see [`payload_entropy`]).
+    pub max_entropy: f64,
+    /// Distinct connected names under one registrable domain considered
+    /// normal. DNS queries are not counted: the detector only sees names of
+    /// sessions.
+    pub max_subdomains: usize,
+    /// DGA score (0 to 1) from which a registrable domain is flagged.
+    pub dga_threshold: f64,
+    /// Domains (wildcards allowed) exempt from the heuristics.
+    pub allow: Vec<String>,
+}
+
+impl Default for DomainOptions {
+    fn default() -> Self {
+        DomainOptions {
+            action: Action::Warn,
+            max_label_length: 40,
+            max_name_length: 100,
+            max_entropy: 4.0,
+            max_subdomains: 100,
+            dga_threshold: 0.6,
+            allow: Vec::new(),
+        }
+    }
+}
+
+/// Shannon entropy of `text` in bits per character.
+pub fn entropy(text: &str) -> f64 {
+    let mut counts: BTreeMap<char, usize> = BTreeMap::new();
+    for c in text.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let total = text.chars().count() as f64;
+    counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Entropy of a subdomain payload in bits per character, normalised to the
+/// 36 letters and digits of DNS names. Hex carries at most 4 bits per
+/// character, so a hex-only payload is scaled by `log2(36) / 4`; otherwise
+/// hex-encoded tunnels would never exceed a threshold set for base32/base64.
+pub fn payload_entropy(payload: &str) -> f64 {
+    let bits = entropy(payload);
+    if payload.chars().all(|c| c.is_ascii_hexdigit()) {
+        bits * 36f64.log2() / 4.0
+    } else {
+        bits
+    }
+}
+
+/// How much a registrable label (`xjkq3hv9azpw` in `xjkq3hv9azpw.com`) looks
+/// machine-generated, from 0 to 1: few vowels, long consonant runs, digits
+/// mixed into letters and near-maximal entropy.
+pub fn dga_score(label: &str) -> f64 {
+    let chars: Vec<char> = label
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if chars.len() < DGA_MIN_LENGTH {
+        return 0.0;
+    }
+    let length = chars.len() as f64;
+    let letters = chars.iter().filter(|c| c.is_ascii_alphabetic()).count();
+    let digits = chars.len() - letters;
+    let vowels = chars.iter().filter(|c| "aeiouy".contains(**c)).count();
+
+    let mut longest_run = 0;
+    let mut run = 0;
+    for c in &chars {
+        if c.is_ascii_alphabetic() && !"aeiouy".contains(*c) {
+            run += 1;
+            longest_run = longest_run.max(run);
+        } else {
+            run = 0;
+        }
+    }
+
+    let vowel_scarcity = ((0.35 - vowels as f64 / length) / 0.35).clamp(0.0, 1.0);
+    let consonant_run = ((longest_run as f64 - 2.0) / 3.0).clamp(0.0, 1.0);
+    let digit_mix = if letters > 0 && digits > 0 {
+        (digits as f64 / length * 3.0).min(1.0)
+    } else {
+        0.0
+    };
+    let label: String = chars.iter().collect();
+    let entropy_ratio = entropy(&label) / length.min(36.0).log2();
+    let randomness = ((entropy_ratio - 0.8) / 0.2).clamp(0.0, 1.0);
+
+    0.35 * vowel_scarcity + 0.25 * consonant_run + 0.2 * digit_mix + 0.2 * randomness
+}
+
+/// Scores the destination names of egress sessions for DNS tunneling and
+/// algorithmically generated domains. Flodbadd does not expose the DNS
+/// queries it captures, so a tunnel that only sends queries and never
+/// connects to the names is invisible here. Keeps state across `watch` polls
+/// so that the names under a domain add up over the run and each finding is
+/// reported once.
+pub struct DomainDetector {
+    options: DomainOptions,
+    psl: PublicSuffixList,
+    /// Names already judged.
+    judged: HashSet<String>,
+    /// Registrable domain -> distinct names seen under it, with the last
+    /// session.
+    parents: BTreeMap<String, (BTreeSet<String>, SessionInfo)>,
+    reported_parents: HashSet<String>,
+    findings: Vec<PolicyViolation>,
+}
+
+impl DomainDetector {
+    pub fn new(options: DomainOptions) -> Self {
+        DomainDetector {
+            options,
+            psl: PublicSuffixList::builtin(),
+            judged: HashSet::new(),
+            parents: BTreeMap::new(),
+            reported_parents: HashSet::new(),
+            findings: Vec::new(),
+        }
+    }
+
+    fn finding(&mut self, rule: &'static str, session: &SessionInfo, detail: String) {
+        self.findings.push(PolicyViolation {
+            rule,
+            session: session.clone(),
+            detail,
+            enforced: self.options.action == Action::Fail,
+        });
+    }
+
+    /// Why the name looks like a tunnel payload, or `None`.
+    pub fn tunneling_reason(&self, name: &str, subdomain: &str) -> Option<String> {
+        if let Some(label) = subdomain
+            .split('.')
+            .find(|l| l.len() > self.options.max_label_length)
+        {
+            return Some(format!("{}-character label {}", label.len(), label));
+        }
+        if name.len() > self.options.max_name_length {
+            return Some(format!("{}-character name", name.len()));
+        }
+        let payload: String = subdomain.chars().filter(|c| *c != '.').collect();
+        if payload.len() >= ENTROPY_MIN_LENGTH {
+            let bits = payload_entropy(&payload);
+            if bits > self.options.max_entropy {
+                return Some(format!("subdomain entropy {:.2} bits/char", bits));
+            }
+        }
+        None
+    }
+
+    /// Scores the names of sessions not seen in earlier calls.
+    pub fn observe(&mut self, sessions: &[SessionInfo]) {
+        if self.options.action == Action::Off {
+            return;
+        }
+        for session in sessions {
+            if !is_egress(session) {
+                continue;
+            }
+            let Some(name) = session.dst_domain.as_deref() else {
+                continue;
+            };
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            if name.is_empty()
+                || UNRESOLVED_DOMAINS
+                    .iter()
+                    .any(|u| u.eq_ignore_ascii_case(&name))
+                || looks_like_reverse_dns(&name, &session.session.dst_ip)
+                || self.options.allow.iter().any(|p| domain_matches(p, &name))
+            {
+                continue;
+            }
+            let Some(parent) = self.psl.registrable_domain(&name) else {
+                continue;
+            };
+
+            let entry = self
+                .parents
+                .entry(parent.clone())
+                .or_insert_with(|| (BTreeSet::new(), session.clone()));
+            entry.0.insert(name.clone());
+            entry.1 = session.clone();
+
+            if !self.judged.insert(name.clone()) {
+                continue;
+            }
+            let subdomain = name
+                .strip_suffix(&parent)
+                .unwrap_or("")
+                .trim_end_matches('.');
+            if let Some(reason) = self.tunneling_reason(&name, subdomain) {
+                self.finding("dns-tunneling", session, format!("{}: {}", name, reason));
+            }
+            let label = parent.split('.').next().unwrap_or("");
+            let score = dga_score(label);
+            if score >= self.options.dga_threshold {
+                self.finding(
+                    "dga",
+                    session,
+                    format!("{} looks generated (score {:.2})", parent, score),
+                );
+            }
+        }
+    }
+
+    /// Findings since the last call, including registrable domains whose
+    /// number of distinct names went over the limit.
+    pub fn detect(&mut self) -> Vec<PolicyViolation> {
+        let mut crowded = Vec::new();
+        for (parent, (names, session)) in &self.parents {
+            if names.len() > self.options.max_subdomains && !self.reported_parents.contains(parent)
+            {
+                crowded.push((parent.clone(), names.len(), session.clone()));
+            }
+        }
+        for (parent, count, session) in crowded {
+            self.reported_parents.insert(parent.clone());
+            self.finding(
+                "dns-tunneling",
+                &session,
+                format!("{} distinct names under {}", count, parent),
+            );
+        }
+        std::mem::take(&mut self.findings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dga_scores() {
+        for generated in ["xjkq3hv9azpw", "qwhxkzrtplmn", "4f8k2j9x7q1z"] {
+            assert!(dga_score(generated) >= 0.6, "{}", generated);
+        }
+        for word in [
+            "githubusercontent",
+            "stackoverflow",
+            "cloudfront",
+            "googleapis",
+            "pythonhosted",
+            "dockerhub",
+            "github",
+        ] {
+            assert!(dga_score(word) < 0.6, "{} {}", word, dga_score(word));
+        }
+    }
+
+    #[test]
+    fn tunnel_payloads() {
+        let detector = DomainDetector::new(DomainOptions::default());
+        let long = "a".repeat(50);
+        assert!(detector
+            .tunneling_reason(&format!("{}.t.example.com", long), &format!("{}.t", long))
+            .is_some());
+        let base32 = "mzxw6ytboi4dkmrvgq3tcnzvhe2dmnbsgi4tanrx";
+        assert!(detector
+            .tunneling_reason(&format!("{}.example.com", base32), base32)
+            .is_some());
+        let hex = "3f9a1c7e5b2d8046e1a9c3f7b5d20e84";
+        assert!(entropy(hex) <= 4.0);
+        assert!(detector
+            .tunneling_reason(&format!("{}.example.com", hex), hex)
+            .is_some());
+        assert!(detector
+            .tunneling_reason("objects.githubusercontent.com", "objects")
+            .is_none());
+        assert!(detector
+            .tunneling_reason("production.cloudflare.docker.com", "production.cloudflare")
+            .is_none());
+        assert!(entropy("aaaa") == 0.0 && (entropy("abcd") - 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,221 @@
+use std::net::IpAddr;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+
+use crate::generalize::PublicSuffixList;
+use crate::whitelist::{WhitelistEndpoint, WhitelistsJSON, WriteOptions};
+
+/// Default location of the exceptions file written by `flodviddar allow`.
+pub const DEFAULT_EXCEPTIONS_FILE: &str = "flodviddar-exceptions.json";
+
+/// Parses a duration such as `90m`, `12h`, `7d` or `2w`. The unit is
+/// required.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .with_context(|| format!("Invalid duration '{}'", value))?;
+    let duration = match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        "" => bail!("Missing duration unit in '{}' (use s, m, h, d or w)", value),
+        _ => bail!("Invalid duration unit in '{}' (use s, m, h, d or w)", value),
+    };
+    match duration {
+        Some(d) if amount > 0 => Ok(d),
+        _ => bail!("Invalid duration '{}'", value),
+    }
+}
+
+/// Checks the target of an exception: an IP, a CIDR other than a /0, or a
+/// domain whose wildcard (`*.example.com`, `api.*.example.com`) ranges over
+/// one label below a registrable domain. `*`, `*.com`, `*.github.io` or
+/// `example.*` would allow unrelated owners.
+pub fn validate_target(target: &str) -> Result<()> {
+    if target.is_empty() || target.chars().any(char::is_whitespace) {
+        bail!("Invalid exception target '{}'", target);
+    }
+    let address = target.split('/').next().unwrap_or(target);
+    if address.parse::<IpAddr>().is_ok() {
+        crate::policy::validate_network(target)?;
+        if target.ends_with("/0") {
+            bail!("Exception target '{}' allows every address", target);
+        }
+        return Ok(());
+    }
+
+    let domain = target.trim_end_matches('.').to_ascii_lowercase();
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.iter().any(|l| {
+        l.is_empty()
+            || (*l != "*"
+                && !l
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    }) {
+        bail!("Invalid exception target '{}'", target);
+    }
+    let wildcards: Vec<usize> = (0..labels.len()).filter(|&i| labels[i] == "*").collect();
+    match wildcards.as_slice() {
+        [] => Ok(()),
+        [at] => {
+            let below = labels[at + 1..].join(".");
+            if PublicSuffixList::builtin()
+                .registrable_domain(&below)
+                .is_none()
+            {
+                bail!(
+                    "Exception target '{}' is broader than a single domain: the wildcard must be followed by a registrable domain (e.g. *.example.com)",
+                    target
+                );
+            }
+            Ok(())
+        }
+        _ => bail!("Exception target '{}' has more than one wildcard", target),
+    }
+}
+
+/// A time-boxed exception requested with `flodviddar allow`.
+pub struct Exception<'a> {
+    pub target: &'a str,
+    pub port: Option<u16>,
+    pub protocol: Option<&'a str>,
+    pub process: Option<&'a str>,
+    pub reason: &'a str,
+    pub owner: Option<&'a str>,
+    pub valid_for: Duration,
+}
+
+impl Exception<'_> {
+    pub fn to_endpoint(&self, now: DateTime<Utc>) -> WhitelistEndpoint {
+        let address = self.target.split('/').next().unwrap_or(self.target);
+        let is_ip = address.parse::<IpAddr>().is_ok();
+        let expires = now + self.valid_for;
+        WhitelistEndpoint {
+            domain: (!is_ip).then(|| self.target.to_ascii_lowercase()),
+            ip: is_ip.then(|| self.target.to_string()),
+            port: self.port,
+            protocol: self.protocol.map(|p| p.to_ascii_uppercase()),
+            process: self.process.map(|p| p.to_string()),
+            description: Some(format!(
+                "Temporary exception added {}",
+                now.to_rfc3339_opts(SecondsFormat::Secs, true)
+            )),
+            justification: Some(self.reason.to_string()),
+            owner: self.owner.map(|o| o.to_string()),
+            expires: Some(expires.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            ..Default::default()
+        }
+    }
+}
+
+/// Appends `exception` to the exceptions file at `path`, creating it when
+/// missing. An existing exception for the same endpoint is renewed instead
+/// of duplicated.
+pub fn allow(path: &str, exception: &Exception) -> Result<WhitelistEndpoint> {
+    validate_target(exception.target)?;
+    let mut exceptions = if std::path::Path::new(path).exists() {
+        WhitelistsJSON::load(path)?
+    } else {
+        WhitelistsJSON::empty()
+    };
+    let endpoint = exception.to_endpoint(Utc::now());
+    let fingerprint = endpoint.fingerprint();
+    let custom = exceptions.custom_whitelist_mut();
+    match custom
+        .endpoints
+        .iter_mut()
+        .find(|e| e.fingerprint() == fingerprint)
+    {
+        Some(existing) => *existing = endpoint.clone(),
+        None => custom.endpoints.push(endpoint.clone()),
+    }
+
+    let contents = if crate::yaml::is_yaml_path(path) {
+        let comments = crate::yaml::load(path).map(|(_, c)| c).unwrap_or_default();
+        crate::yaml::to_yaml(&exceptions, &comments, &WriteOptions::default())
+    } else {
+        exceptions.to_canonical_json(&WriteOptions::default())?
+    };
+    std::fs::write(path, contents)
+        .with_context(|| format!("Failed to write exceptions file '{}'", path))?;
+    Ok(endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("7d").unwrap(), Duration::days(7));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::weeks(2));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("0d").is_err());
+        assert!(parse_duration("7y").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+
+    #[test]
+    fn targets() {
+        for target in [
+            "files.pythonhosted.org",
+            "*.pythonhosted.org",
+            "api.*.example.com",
+            "*.cdn.example.co.uk",
+            "10.1.0.0/16",
+            "2606:50c0::/32",
+        ] {
+            assert!(validate_target(target).is_ok(), "{}", target);
+        }
+        for target in [
+            "",
+            "*",
+            "*.com",
+            "*.co.uk",
+            "*.github.io",
+            "example.*",
+            "*.*.example.com",
+            "exa mple.com",
+            "0.0.0.0/0",
+            "10.0.0.0/33",
+        ] {
+            assert!(validate_target(target).is_err(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn exception_endpoint() {
+        let exception = Exception {
+            target: "10.1.0.0/16",
+            port: Some(5432),
+            protocol: Some("tcp"),
+            process: None,
+            reason: "Migration tests",
+            owner: Some("db-team"),
+            valid_for: Duration::days(7),
+        };
+        let now = DateTime::parse_from_rfc3339("2025-07-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let endpoint = exception.to_endpoint(now);
+        assert_eq!(endpoint.ip.as_deref(), Some("10.1.0.0/16"));
+        assert_eq!(endpoint.domain, None);
+        assert_eq!(endpoint.protocol.as_deref(), Some("TCP"));
+        assert_eq!(endpoint.expires.as_deref(), Some("2025-07-08T10:00:00Z"));
+        assert!(!endpoint.generated);
+        assert_eq!(
+            endpoint.expires_at().unwrap(),
+            Some(now + Duration::days(7))
+        );
+    }
+}
@@ -0,0 +1,596 @@
+use std::net::IpAddr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use flodbadd::sessions::{SessionInfo, WhitelistState};
+
+use crate::whitelist::{
+    domain_matches, ip_matches, is_cdn_owner, WhitelistEndpoint, WhitelistsJSON,
+    CUSTOM_WHITELIST_NAME,
+};
+
+/// How the destination domain of a session was obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    ForwardDns,
+    Sni,
+    ReverseDns,
+    /// The domain came from a saved report, which does not record its source.
+    Unrecorded,
+    None,
+}
+
+impl Resolution {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "forward-dns" => Ok(Resolution::ForwardDns),
+            "sni" => Ok(Resolution::Sni),
+            "reverse-dns" => Ok(Resolution::ReverseDns),
+            _ => bail!("Unknown resolution '{}'", value),
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Resolution::ForwardDns => "Forward DNS (captured DNS answer)",
+            Resolution::Sni => "SNI (TLS ClientHello)",
+            Resolution::ReverseDns => "reverse DNS (PTR lookup, unreliable for CDNs)",
+            Resolution::Unrecorded => "Forward DNS or SNI (source not recorded in report)",
+            Resolution::None => "none (destination is a bare IP)",
+        }
+    }
+
+    fn is_reliable(&self) -> bool {
+        matches!(
+            self,
+            Resolution::ForwardDns | Resolution::Sni | Resolution::Unrecorded
+        )
+    }
+}
+
+/// The attributes of a session that take part in whitelist matching.
+#[derive(Debug, Clone)]
+pub struct Subject {
+    pub protocol: String,
+    pub src: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub port: u16,
+    pub domain: Option<String>,
+    pub resolution: Resolution,
+    pub process: Option<String>,
+    pub as_number: Option<u32>,
+    pub as_country: Option<String>,
+    pub as_owner: Option<String>,
+    pub egress: bool,
+    /// The verdict and reason the engine recorded for a saved session.
+    pub recorded: Option<(WhitelistState, Option<String>)>,
+}
+
+impl Subject {
+    /// Builds a subject from a `host:port` destination tuple. `host` may be a
+    /// domain or an IP address (IPv6 in brackets).
+    pub fn from_tuple(target: &str) -> Result<Self> {
+        let (host, port) = target
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("Destination '{}' must be host:port", target))?;
+        let port: u16 = port
+            .parse()
+            .with_context(|| format!("Invalid port in '{}'", target))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let (ip, domain) = match host.parse::<IpAddr>() {
+            Ok(ip) => (Some(ip), None),
+            Err(_) => (None, Some(host.to_string())),
+        };
+        Ok(Subject {
+            protocol: "TCP".to_string(),
+            src: None,
+            ip,
+            port,
+            resolution: if domain.is_some() {
+                Resolution::ForwardDns
+            } else {
+                Resolution::None
+            },
+            domain,
+            process: None,
+            as_number: None,
+            as_country: None,
+            as_owner: None,
+            egress: true,
+            recorded: None,
+        })
+    }
+
+    /// Builds a subject from one session of a saved `scan --output report`,
+    /// e.g. a line of `jq -c '.[]' report.json`.
+    pub fn from_session(session: &SessionInfo) -> Self {
+        let ip = session.session.dst_ip;
+        let domain = session.dst_domain.clone();
+        let resolution = match &domain {
+            Some(d) if looks_like_reverse_dns(d, &ip) => Resolution::ReverseDns,
+            Some(_) => Resolution::Unrecorded,
+            None => Resolution::None,
+        };
+        Subject {
+            protocol: format!("{:?}", session.session.protocol),
+            src: Some(format!(
+                "{}:{}",
+                session.session.src_ip, session.session.src_port
+            )),
+            ip: Some(ip),
+            port: session.session.dst_port,
+            domain,
+            resolution,
+            process: session.l7.as_ref().map(|l7| l7.process_name.clone()),
+            as_number: session.dst_asn.as_ref().map(|a| a.as_number),
+            as_country: session.dst_asn.as_ref().map(|a| a.country.clone()),
+            as_owner: session.dst_asn.as_ref().map(|a| a.owner.clone()),
+            egress: session.is_self_src || (session.is_local_src && !session.is_local_dst),
+            recorded: Some((
+                session.is_whitelisted.clone(),
+                session.whitelist_reason.clone(),
+            )),
+        }
+    }
+
+    fn is_cdn(&self) -> bool {
+        self.as_owner.as_deref().map(is_cdn_owner).unwrap_or(false)
+    }
+}
+
+/// PTR names of CDN edges embed the address, e.g. `cdn-185-199-111-133.github.com`.
+pub fn looks_like_reverse_dns(domain: &str, ip: &IpAddr) -> bool {
+    let IpAddr::V4(v4) = ip else {
+        return false;
+    };
+    let octets = v4.octets();
+    let dashed = format!("{}-{}-{}-{}", octets[0], octets[1], octets[2], octets[3]);
+    let reversed = format!("{}-{}-{}-{}", octets[3], octets[2], octets[1], octets[0]);
+    let dotted_reversed = format!("{}.{}.{}.{}", octets[3], octets[2], octets[1], octets[0]);
+    domain.contains(&dashed) || domain.contains(&reversed) || domain.contains(&dotted_reversed)
+}
+
+/// Result of evaluating one whitelist endpoint against a subject.
+#[derive(Debug)]
+pub struct EndpointTrace {
+    pub whitelist: String,
+    pub label: String,
+    pub checks: Vec<Check>,
+    pub matched: bool,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl EndpointTrace {
+    fn passed_l4(&self) -> bool {
+        self.checks
+            .iter()
+            .filter(|c| c.name == "protocol" || c.name == "port")
+            .all(|c| c.ok)
+    }
+}
+
+/// Evaluates a single endpoint following the documented hierarchy:
+/// protocol/port/process first, then domain, then IP/CIDR (subject to the CDN
+/// guard), then ASN.
+pub fn trace_endpoint(
+    whitelist: &str,
+    endpoint: &WhitelistEndpoint,
+    subject: &Subject,
+) -> EndpointTrace {
+    let mut checks: Vec<Check> = Vec::new();
+    let mut push = |name: &'static str, ok: bool, detail: String| {
+        checks.push(Check { name, ok, detail });
+        ok
+    };
+
+    let mut required_ok = true;
+    if let Some(protocol) = &endpoint.protocol {
+        let ok = protocol.eq_ignore_ascii_case(&subject.protocol);
+        required_ok &= push(
+            "protocol",
+            ok,
+            format!("expected {}, got {}", protocol, subject.protocol),
+        );
+    }
+    if let Some(port) = endpoint.port {
+        let ok = port == subject.port;
+        required_ok &= push(
+            "port",
+            ok,
+            format!("expected {}, got {}", port, subject.port),
+        );
+    }
+    if let Some(process) = &endpoint.process {
+        let ok = subject
+            .process
+            .as_deref()
+            .map(|p| p.eq_ignore_ascii_case(process))
+            .unwrap_or(false);
+        required_ok &= push(
+            "process",
+            ok,
+            format!(
+                "expected {}, got {}",
+                process,
+                fmt_opt(subject.process.clone())
+            ),
+        );
+    }
+
+    // Domain has priority; the IP entry is only consulted when it fails.
+    let domain_ok = endpoint
+        .domain
+        .as_ref()
+        .map(|pattern| match &subject.domain {
+            Some(domain) if !subject.resolution.is_reliable() && subject.is_cdn() => push(
+                "domain",
+                false,
+                format!(
+                    "{} vs {} rejected: {} names are not trusted on CDN addresses",
+                    pattern,
+                    domain,
+                    subject.resolution.describe()
+                ),
+            ),
+            Some(domain) => push(
+                "domain",
+                domain_matches(pattern, domain),
+                format!(
+                    "{} vs {} via {}",
+                    pattern,
+                    domain,
+                    subject.resolution.describe()
+                ),
+            ),
+            None => push(
+                "domain",
+                false,
+                format!("{} vs unresolved destination", pattern),
+            ),
+        });
+    let ip_ok = match (&endpoint.ip, domain_ok) {
+        (Some(pattern), None | Some(false)) => {
+            let got = subject
+                .ip
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| "<unknown>".to_string());
+            let matches = subject
+                .ip
+                .map(|ip| ip_matches(pattern, &ip))
+                .unwrap_or(false);
+            if matches && endpoint.domain.is_some() && subject.is_cdn() {
+                Some(push(
+                    "ip",
+                    false,
+                    format!(
+                        "{} matches {} but CDN guard rejected it: {} address space is shared, a domain match is required",
+                        pattern,
+                        got,
+                        subject.as_owner.as_deref().unwrap_or("CDN")
+                    ),
+                ))
+            } else {
+                Some(push(
+                    "ip",
+                    matches,
+                    format!("expected {}, got {}", pattern, got),
+                ))
+            }
+        }
+        _ => None,
+    };
+    let destination_ok = match (domain_ok, ip_ok) {
+        (Some(true), _) => true,
+        (_, Some(ok)) => ok,
+        (Some(ok), None) => ok,
+        (None, None) => true,
+    };
+
+    if let Some(as_number) = endpoint.as_number {
+        let ok = subject.as_number == Some(as_number);
+        required_ok &= push(
+            "as_number",
+            ok,
+            format!(
+                "expected AS{}, got {}",
+                as_number,
+                fmt_opt(subject.as_number.map(|n| format!("AS{}", n)))
+            ),
+        );
+    }
+    if let Some(country) = &endpoint.as_country {
+        let ok = subject
+            .as_country
+            .as_deref()
+            .map(|c| c.eq_ignore_ascii_case(country))
+            .unwrap_or(false);
+        required_ok &= push(
+            "as_country",
+            ok,
+            format!(
+                "expected {}, got {}",
+                country,
+                fmt_opt(subject.as_country.clone())
+            ),
+        );
+    }
+    if let Some(owner) = &endpoint.as_owner {
+        let ok = subject
+            .as_owner
+            .as_deref()
+            .map(|o| o.eq_ignore_ascii_case(owner))
+            .unwrap_or(false);
+        required_ok &= push(
+            "as_owner",
+            ok,
+            format!(
+                "expected {}, got {}",
+                owner,
+                fmt_opt(subject.as_owner.clone())
+            ),
+        );
+    }
+
+    EndpointTrace {
+        whitelist: whitelist.to_string(),
+        label: endpoint.label(),
+        checks,
+        matched: required_ok && destination_ok,
+    }
+}
+
+fn fmt_opt(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "<unknown>".to_string())
+}
+
+/// Whether the engine's recorded verdict contradicts the trace. `Unknown`
+/// (not evaluated) contradicts nothing.
+fn disagrees(recorded: &WhitelistState, conforming: bool) -> bool {
+    match recorded {
+        WhitelistState::Conforming => !conforming,
+        WhitelistState::NonConforming => conforming,
+        WhitelistState::Unknown => false,
+    }
+}
+
+/// Prints the matching trace of `subject` against the custom whitelist, then
+/// the verdict the engine recorded for the session when there is one. The
+/// trace mirrors the engine's matching rather than calling it, so a
+/// disagreement is flagged and the recorded verdict is the authoritative one.
+pub fn explain(whitelists: &WhitelistsJSON, subject: &Subject, show_all: bool) -> bool {
+    let conforming = trace(whitelists, subject, show_all);
+    if let Some((state, reason)) = &subject.recorded {
+        println!(
+            "Engine verdict: {}{}",
+            match state {
+                WhitelistState::Conforming => "CONFORMING",
+                WhitelistState::NonConforming => "NON-CONFORMING",
+                WhitelistState::Unknown => "UNKNOWN",
+            },
+            reason
+                .as_deref()
+                .map(|r| format!(" ({})", r))
+                .unwrap_or_default()
+        );
+        if disagrees(state, conforming) {
+            println!(
+                "WARNING: the trace disagrees with the engine's recorded verdict; \
+                 the recorded verdict is what scan enforced"
+            );
+        }
+    }
+    conforming
+}
+
+fn trace(whitelists: &WhitelistsJSON, subject: &Subject, show_all: bool) -> bool {
+    println!(
+        "Session: {} {} -> {}{}:{}",
+        subject.protocol,
+        subject.src.as_deref().unwrap_or("<local>"),
+        subject
+            .domain
+            .as_deref()
+            .map(|d| format!("{} ", d))
+            .unwrap_or_default(),
+        subject.ip.map(|ip| format!("({})", ip)).unwrap_or_default(),
+        subject.port
+    );
+    println!(
+        "Process: {}",
+        subject.process.as_deref().unwrap_or("<unknown>")
+    );
+    println!("Domain resolution: {}", subject.resolution.describe());
+    if let Some(owner) = &subject.as_owner {
+        println!(
+            "ASN: {} {}{}",
+            fmt_opt(subject.as_number.map(|n| format!("AS{}", n))),
+            owner,
+            if subject.is_cdn() {
+                " (CDN provider)"
+            } else {
+                ""
+            }
+        );
+    }
+
+    if !subject.egress {
+        println!("Direction: inbound – egress-only evaluation marks it conforming");
+        println!("\nVerdict: CONFORMING");
+        return true;
+    }
+    println!("Direction: egress – evaluated");
+
+    let endpoints = whitelists.resolve_endpoints(CUSTOM_WHITELIST_NAME);
+    if endpoints.is_empty() {
+        println!("\nWhitelist '{}' not found or empty", CUSTOM_WHITELIST_NAME);
+        println!("\nVerdict: NON-CONFORMING (no endpoints)");
+        return false;
+    }
+
+    let traces: Vec<EndpointTrace> = endpoints
+        .iter()
+        .map(|(name, endpoint)| trace_endpoint(name, endpoint, subject))
+        .collect();
+    let skipped = traces.iter().filter(|t| !t.passed_l4()).count();
+
+    println!(
+        "\nConsidered {} endpoints ({} rejected on protocol/port{})",
+        traces.len(),
+        skipped,
+        if show_all {
+            ""
+        } else {
+            ", hidden; use --all to show"
+        }
+    );
+    for trace in traces.iter().filter(|t| show_all || t.passed_l4()) {
+        println!(
+            "\n  [{}] {} – {}",
+            trace.whitelist,
+            trace.label,
+            if trace.matched { "MATCH" } else { "no match" }
+        );
+        for check in &trace.checks {
+            println!(
+                "    {:<10} {} {}",
+                check.name,
+                if check.ok { "ok  " } else { "FAIL" },
+                check.detail
+            );
+        }
+    }
+
+    match traces.iter().find(|t| t.matched) {
+        Some(trace) => {
+            println!(
+                "\nVerdict: CONFORMING (matched [{}] {})",
+                trace.whitelist, trace.label
+            );
+            true
+        }
+        None => {
+            println!("\nVerdict: NON-CONFORMING (no endpoint matched)");
+            false
+        }
+    }
+}
+
+/// Parses `--session`, which is either an inline JSON session object or a
+/// path to a file containing one.
+pub fn load_session(value: &str) -> Result<SessionInfo> {
+    let json = if value.trim_start().starts_with('{') {
+        value.to_string()
+    } else {
+        std::fs::read_to_string(value)
+            .with_context(|| format!("Failed to read session '{}'", value))?
+    };
+    serde_json::from_str(&json).context("Failed to parse session JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subject(domain: Option<&str>, ip: &str, owner: Option<&str>) -> Subject {
+        Subject {
+            protocol: "TCP".to_string(),
+            src: None,
+            ip: Some(ip.parse().unwrap()),
+            port: 443,
+            domain: domain.map(|d| d.to_string()),
+            resolution: if domain.is_some() {
+                Resolution::Sni
+            } else {
+                Resolution::None
+            },
+            process: Some("curl".to_string()),
+            as_number: None,
+            as_country: None,
+            as_owner: owner.map(|o| o.to_string()),
+            egress: true,
+            recorded: None,
+        }
+    }
+
+    fn endpoint(domain: Option<&str>, ip: Option<&str>) -> WhitelistEndpoint {
+        WhitelistEndpoint {
+            domain: domain.map(|d| d.to_string()),
+            ip: ip.map(|i| i.to_string()),
+            port: Some(443),
+            protocol: Some("TCP".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn domain_match() {
+        let trace = trace_endpoint(
+            "wl",
+            &endpoint(Some("api.github.com"), None),
+            &subject(Some("api.github.com"), "140.82.112.6", None),
+        );
+        assert!(trace.matched);
+    }
+
+    #[test]
+    fn ip_stands_in_for_domain() {
+        let trace = trace_endpoint(
+            "wl",
+            &endpoint(Some("api.github.com"), Some("140.82.112.0/20")),
+            &subject(None, "140.82.112.6", Some("GITHUB")),
+        );
+        assert!(trace.matched);
+    }
+
+    #[test]
+    fn cdn_guard_rejects_ip_match() {
+        let trace = trace_endpoint(
+            "wl",
+            &endpoint(
+                Some("objects.githubusercontent.com"),
+                Some("185.199.108.133"),
+            ),
+            &subject(
+                Some("gist.githubusercontent.com"),
+                "185.199.108.133",
+                Some("FASTLY"),
+            ),
+        );
+        assert!(!trace.matched);
+        assert!(trace.checks.iter().any(|c| c.detail.contains("CDN guard")));
+    }
+
+    #[test]
+    fn process_mismatch() {
+        let mut ep = endpoint(Some("api.github.com"), None);
+        ep.process = Some("git".to_string());
+        let trace = trace_endpoint(
+            "wl",
+            &ep,
+            &subject(Some("api.github.com"), "140.82.112.6", None),
+        );
+        assert!(!trace.matched);
+    }
+
+    #[test]
+    fn tuple_parsing() {
+        let s = Subject::from_tuple("[2606:50c0:8000::154]:443").unwrap();
+        assert!(s.ip.is_some() && s.domain.is_none());
+        let s = Subject::from_tuple("api.github.com:443").unwrap();
+        assert_eq!(s.domain.as_deref(), Some("api.github.com"));
+        assert_eq!(s.resolution, Resolution::ForwardDns);
+    }
+
+    #[test]
+    fn engine_disagreement() {
+        assert!(disagrees(&WhitelistState::NonConforming, true));
+        assert!(disagrees(&WhitelistState::Conforming, false));
+        assert!(!disagrees(&WhitelistState::Conforming, true));
+        assert!(!disagrees(&WhitelistState::Unknown, false));
+    }
+}
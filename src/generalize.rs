@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use anyhow::{Context, Result};
+
+use crate::whitelist::{WhitelistEndpoint, WhitelistInfo};
+
+/// The Public Suffix List compiled into the binary (snapshot of
+/// 2023-02-09). Refresh `publicsuffix/public_suffix_list.dat` from
+/// https://publicsuffix.org/list/public_suffix_list.dat, or load a newer
+/// copy with `--public-suffix-list`.
+const BUILTIN_SUFFIXES: &str = include_str!("../publicsuffix/public_suffix_list.dat");
+
+/// Minimal Public Suffix List matcher (https://publicsuffix.org/list/).
+///
+/// Both the ICANN and the private sections are honoured: private entries
+/// such as `s3.amazonaws.com` or `github.io` host many tenants, and a
+/// `*.github.io` entry would allow every one of them.
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    wildcards: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_SUFFIXES)
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read public suffix list '{}'", path))?;
+        Ok(Self::parse(&data))
+    }
+
+    pub fn parse(data: &str) -> Self {
+        let mut psl = PublicSuffixList {
+            rules: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new(),
+        };
+        for line in data.lines() {
+            let line = line.trim();
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+            let rule = rule.to_ascii_lowercase();
+            if let Some(exception) = rule.strip_prefix('!') {
+                psl.exceptions.insert(exception.to_string());
+            } else if let Some(parent) = rule.strip_prefix("*.") {
+                psl.wildcards.insert(parent.to_string());
+            } else {
+                psl.rules.insert(rule);
+            }
+        }
+        psl
+    }
+
+    /// Number of labels of the public suffix of `domain`.
+    fn suffix_len(&self, labels: &[&str]) -> usize {
+        let mut longest = 1; // implicit "*" rule
+        for i in 0..labels.len() {
+            let candidate = labels[i..].join(".");
+            let len = labels.len() - i;
+            if self.exceptions.contains(&candidate) {
+                return len - 1;
+            }
+            if self.rules.contains(&candidate) {
+                longest = longest.max(len);
+            }
+            if i + 1 < labels.len() && self.wildcards.contains(&labels[i + 1..].join(".")) {
+                longest = longest.max(len);
+            }
+        }
+        longest
+    }
+
+    /// The registrable domain (public suffix plus one label), or `None` when
+    /// the domain is itself a public suffix.
+    pub fn registrable_domain(&self, domain: &str) -> Option<String> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let labels: Vec<&str> = domain.split('.').collect();
+        let suffix = self.suffix_len(&labels);
+        if labels.len() <= suffix {
+            return None;
+        }
+        Some(labels[labels.len() - suffix - 1..].join("."))
+    }
+}
+
+/// A wildcard entry proposed in place of sibling subdomain entries.
+pub struct Generalization {
+    pub wildcard: String,
+    pub replaced: Vec<String>,
+}
+
+/// Folds groups of at least `min_siblings` subdomains that share a registrable
+/// domain (and port, protocol and process) into a single `*.<registrable>`
+/// entry. The apex domain itself is kept, since `*.` only matches subdomains.
+pub fn generalize(
+    whitelist: &mut WhitelistInfo,
+    psl: &PublicSuffixList,
+    min_siblings: usize,
+) -> Vec<Generalization> {
+    type GroupKey = (String, Option<u16>, Option<String>, Option<String>);
+    let mut groups: BTreeMap<GroupKey, BTreeSet<String>> = BTreeMap::new();
+
+    for endpoint in &whitelist.endpoints {
+        let Some(domain) = &endpoint.domain else {
+            continue;
+        };
+        if domain.contains('*') {
+            continue;
+        }
+        let Some(registrable) = psl.registrable_domain(domain) else {
+            continue;
+        };
+        if domain.eq_ignore_ascii_case(&registrable) {
+            continue;
+        }
+        groups
+            .entry((
+                registrable,
+                endpoint.port,
+                endpoint.protocol.clone(),
+                endpoint.process.clone(),
+            ))
+            .or_default()
+            .insert(domain.to_ascii_lowercase());
+    }
+
+    let mut generalizations = Vec::new();
+    for ((registrable, port, protocol, process), domains) in groups {
+        if domains.len() < min_siblings.max(2) {
+            continue;
+        }
+        let wildcard = format!("*.{}", registrable);
+        let in_group = |e: &WhitelistEndpoint| {
+            e.port == port
+                && e.protocol == protocol
+                && e.process == process
+                && e.domain
+                    .as_ref()
+                    .map(|d| domains.contains(&d.to_ascii_lowercase()))
+                    .unwrap_or(false)
+        };
+        let Some(first) = whitelist.endpoints.iter().position(in_group) else {
+            continue;
+        };
+        let replaced: Vec<String> = domains.iter().cloned().collect();
+        let merged = WhitelistEndpoint {
+            domain: Some(wildcard.clone()),
+            port,
+            protocol: protocol.clone(),
+            process: process.clone(),
+            description: Some(format!(
+                "Generalized from {} subdomains: {}",
+                replaced.len(),
+                replaced.join(", ")
+            )),
+            generated: true,
+            ..Default::default()
+        };
+        // The merged entry takes the position of the first sibling, so the
+        // output order stays close to the generated one.
+        whitelist.endpoints[first] = merged;
+        whitelist.endpoints.retain(|e| !in_group(e));
+        generalizations.push(Generalization { wildcard, replaced });
+    }
+    generalizations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(domain: &str) -> WhitelistEndpoint {
+        WhitelistEndpoint {
+            domain: Some(domain.to_string()),
+            port: Some(443),
+            protocol: Some("TCP".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn registrable_domains() {
+        let psl = PublicSuffixList::builtin();
+        assert_eq!(
+            psl.registrable_domain("a.b.example.co.uk").as_deref(),
+            Some("example.co.uk")
+        );
+        assert_eq!(psl.registrable_domain("co.uk"), None);
+        assert_eq!(
+            psl.registrable_domain("foo.bar.ck").as_deref(),
+            Some("foo.bar.ck")
+        );
+        assert_eq!(psl.registrable_domain("www.ck").as_deref(), Some("www.ck"));
+        assert_eq!(
+            psl.registrable_domain("objects-origin-1.githubusercontent.com")
+                .as_deref(),
+            Some("objects-origin-1.githubusercontent.com")
+        );
+    }
+
+    #[test]
+    fn shared_hosting_suffixes_do_not_fold() {
+        let psl = PublicSuffixList::builtin();
+        assert_eq!(
+            psl.registrable_domain("bucket.s3.amazonaws.com").as_deref(),
+            Some("bucket.s3.amazonaws.com")
+        );
+        let mut wl = WhitelistInfo {
+            name: "custom_whitelist".to_string(),
+            extends: None,
+            endpoints: vec![
+                endpoint("bucket.s3.amazonaws.com"),
+                endpoint("other.s3.amazonaws.com"),
+                endpoint("third.s3.amazonaws.com"),
+                endpoint("user.github.io"),
+                endpoint("org.github.io"),
+                endpoint("team.github.io"),
+                endpoint("objects-origin-1.githubusercontent.com"),
+                endpoint("objects-origin-2.githubusercontent.com"),
+            ],
+        };
+        assert!(generalize(&mut wl, &psl, 2).is_empty());
+        assert_eq!(wl.endpoints.len(), 8);
+        assert!(!wl
+            .endpoints
+            .iter()
+            .any(|e| e.domain.as_deref() == Some("*.amazonaws.com")));
+    }
+
+    #[test]
+    fn folds_siblings_above_threshold() {
+        let mut wl = WhitelistInfo {
+            name: "custom_whitelist".to_string(),
+            extends: None,
+            endpoints: vec![
+                endpoint("cdn-1.example.com"),
+                endpoint("cdn-2.example.com"),
+                endpoint("cdn-3.example.com"),
+                endpoint("example.com"),
+                endpoint("api.github.com"),
+                endpoint("codeload.github.com"),
+            ],
+        };
+        let result = generalize(&mut wl, &PublicSuffixList::builtin(), 3);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].wildcard, "*.example.com");
+        let domains: Vec<&str> = wl
+            .endpoints
+            .iter()
+            .filter_map(|e| e.domain.as_deref())
+            .collect();
+        assert_eq!(
+            domains,
+            vec![
+                "*.example.com",
+                "example.com",
+                "api.github.com",
+                "codeload.github.com"
+            ]
+        );
+    }
+}
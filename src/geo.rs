@@ -0,0 +1,129 @@
+use std::net::IpAddr;
+
+use anyhow::{bail, Context, Result};
+
+/// AS and country of an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsnInfo {
+    pub as_number: u32,
+    pub country: String,
+    pub owner: String,
+}
+
+#[derive(Debug, Clone)]
+struct Range {
+    start: (bool, u128),
+    end: (bool, u128),
+    info: AsnInfo,
+}
+
+/// Offline IP-to-ASN/country database in the iptoasn.com TSV layout
+/// (`range_start  range_end  AS_number  country_code  AS_description`), as
+/// published in `ip2asn-v4.tsv`, `ip2asn-v6.tsv` and `ip2asn-combined.tsv`.
+#[derive(Debug, Clone, Default)]
+pub struct AsnDatabase {
+    ranges: Vec<Range>,
+}
+
+/// Orders IPv4 before IPv6 and addresses numerically within a family.
+fn key(ip: &IpAddr) -> (bool, u128) {
+    match ip {
+        IpAddr::V4(v4) => (false, u32::from(*v4) as u128),
+        IpAddr::V6(v6) => (true, u128::from(*v6)),
+    }
+}
+
+impl AsnDatabase {
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let mut database = AsnDatabase::default();
+        for path in paths {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read ASN database '{}'", path))?;
+            let before = database.ranges.len();
+            database
+                .add(&text)
+                .with_context(|| format!("Failed to parse ASN database '{}'", path))?;
+            println!(
+                "Loaded {} ranges from ASN database {}",
+                database.ranges.len() - before,
+                path
+            );
+        }
+        database.ranges.sort_by_key(|r| r.start);
+        Ok(database)
+    }
+
+    /// Adds the ranges of a TSV file. Unrouted ranges (AS 0) are skipped.
+    /// Call [`AsnDatabase::load`] or sort before looking up.
+    fn add(&mut self, text: &str) -> Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 5 {
+                bail!("line {}: expected 5 tab-separated fields", number + 1);
+            }
+            let parse = |value: &str| -> Result<IpAddr> {
+                value
+                    .parse()
+                    .with_context(|| format!("line {}: invalid address '{}'", number + 1, value))
+            };
+            let (start, end) = (parse(fields[0])?, parse(fields[1])?);
+            let as_number: u32 = fields[2]
+                .parse()
+                .with_context(|| format!("line {}: invalid AS number", number + 1))?;
+            if as_number == 0 {
+                continue;
+            }
+            self.ranges.push(Range {
+                start: key(&start),
+                end: key(&end),
+                info: AsnInfo {
+                    as_number,
+                    country: fields[3].to_ascii_uppercase(),
+                    owner: fields[4].to_string(),
+                },
+            });
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, ip: &IpAddr) -> Option<&AsnInfo> {
+        let ip = key(ip);
+        let index = self.ranges.partition_point(|r| r.start <= ip);
+        let range = self.ranges.get(index.checked_sub(1)?)?;
+        (ip <= range.end).then_some(&range.info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_by_range() {
+        let mut database = AsnDatabase::default();
+        database
+            .add(
+                "1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+                 1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n\
+                 5.8.0.0\t5.8.255.255\t12345\tru\tEXAMPLE-AS\n\
+                 2001:db8::\t2001:db8:ffff:ffff:ffff:ffff:ffff:ffff\t64500\tDE\tDOC-NET\n",
+            )
+            .unwrap();
+        database.ranges.sort_by_key(|r| r.start);
+
+        let info = database.lookup(&"5.8.10.1".parse().unwrap()).unwrap();
+        assert_eq!((info.as_number, info.country.as_str()), (12345, "RU"));
+        assert!(database.lookup(&"1.0.2.1".parse().unwrap()).is_none());
+        assert!(database.lookup(&"9.9.9.9".parse().unwrap()).is_none());
+        assert_eq!(
+            database
+                .lookup(&"2001:db8::1".parse().unwrap())
+                .map(|i| i.as_number),
+            Some(64500)
+        );
+        assert!(database.add("1.0.0.0 1.0.0.255 13335").is_err());
+    }
+}
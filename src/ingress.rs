@@ -0,0 +1,338 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::Result;
+use flodbadd::sessions::SessionInfo;
+
+use crate::policy::{validate_network, Action};
+use crate::whitelist::ip_matches;
+
+/// An `--ingress-allow` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IngressAllow {
+    /// Local port, e.g. a test server on 8080.
+    Port(u16),
+    /// Source network of inbound sessions.
+    Network(String),
+    /// Process name or path, on any port.
+    Process(String),
+    /// Process on one port, e.g. `node:3000`.
+    ProcessPort(String, u16),
+}
+
+impl IngressAllow {
+    pub fn parse(value: &str) -> Self {
+        if let Ok(port) = value.parse::<u16>() {
+            return IngressAllow::Port(port);
+        }
+        if validate_network(value).is_ok() {
+            return IngressAllow::Network(value.to_string());
+        }
+        if let Some((process, port)) = value.rsplit_once(':') {
+            if let Ok(port) = port.parse::<u16>() {
+                return IngressAllow::ProcessPort(process.to_string(), port);
+            }
+        }
+        IngressAllow::Process(value.to_string())
+    }
+
+    fn allows(&self, port: u16, process: Option<(&str, &str)>, source: Option<&IpAddr>) -> bool {
+        let is_process = |p: &str| process.is_some_and(|(name, path)| p == name || p == path);
+        match self {
+            IngressAllow::Port(allowed) => *allowed == port,
+            IngressAllow::Network(network) => source.is_some_and(|ip| ip_matches(network, ip)),
+            IngressAllow::Process(p) => is_process(p),
+            IngressAllow::ProcessPort(p, allowed) => *allowed == port && is_process(p),
+        }
+    }
+}
+
+/// Ingress policy: new listening sockets and inbound sessions from outside
+/// the local network.
+#[derive(Debug, Clone, Default)]
+pub struct IngressOptions {
+    pub action: Action,
+    pub allow: Vec<IngressAllow>,
+}
+
+impl IngressOptions {
+    pub fn is_allowed(
+        &self,
+        port: u16,
+        process: Option<(&str, &str)>,
+        source: Option<&IpAddr>,
+    ) -> bool {
+        self.allow.iter().any(|a| a.allows(port, process, source))
+    }
+}
+
+/// Whether a session was opened from a non-local address towards this host.
+pub fn is_inbound(session: &SessionInfo) -> bool {
+    session.is_self_dst && !session.is_local_src && !session.is_self_src
+}
+
+/// A TCP socket in the LISTEN state.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Listener {
+    pub address: IpAddr,
+    pub port: u16,
+    pub pid: Option<u32>,
+    pub process: Option<String>,
+    pub path: Option<String>,
+}
+
+impl Listener {
+    fn label(&self) -> String {
+        let address = match self.address {
+            IpAddr::V6(v6) => format!("[{}]", v6),
+            v4 => v4.to_string(),
+        };
+        let owner = match (&self.process, self.pid) {
+            (Some(process), Some(pid)) => format!("{} (pid {})", process, pid),
+            _ => "unknown process".to_string(),
+        };
+        format!("{}:{}/TCP by {}", address, self.port, owner)
+    }
+}
+
+/// Parses an address of `/proc/net/tcp` (`0100007F:0035`) or
+/// `/proc/net/tcp6`, stored as native-endian 32-bit words.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_address(value: &str) -> Option<(IpAddr, u16)> {
+    let (address, port) = value.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let words: Vec<u32> = (0..address.len() / 8)
+        .map(|i| u32::from_str_radix(&address[i * 8..i * 8 + 8], 16))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let ip = match words.as_slice() {
+        [a] => IpAddr::V4(Ipv4Addr::from(a.to_ne_bytes())),
+        [a, b, c, d] => {
+            let mut octets = [0u8; 16];
+            for (i, word) in [a, b, c, d].iter().enumerate() {
+                octets[i * 4..i * 4 + 4].copy_from_slice(&word.to_ne_bytes());
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some((ip, port))
+}
+
+/// Listening sockets of a `/proc/net/tcp[6]` table with their inode.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_net(table: &str) -> Vec<(IpAddr, u16, u64)> {
+    const LISTEN: &str = "0A";
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 || fields[3] != LISTEN {
+                return None;
+            }
+            let (ip, port) = parse_proc_address(fields[1])?;
+            Some((ip, port, fields[9].parse().ok()?))
+        })
+        .collect()
+}
+
+/// Non-loopback listening TCP sockets, with the owning process when it can
+/// be read from `/proc/<pid>/fd`.
+#[cfg(target_os = "linux")]
+pub fn listeners() -> Result<Vec<Listener>> {
+    use std::collections::HashMap;
+
+    let mut sockets = Vec::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        if let Ok(text) = std::fs::read_to_string(table) {
+            sockets.extend(parse_proc_net(&text));
+        }
+    }
+    sockets.retain(|(ip, _, _)| !ip.is_loopback());
+
+    let wanted: HashSet<u64> = sockets.iter().map(|(_, _, inode)| *inode).collect();
+    let mut owners: HashMap<u64, u32> = HashMap::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let target = target.to_string_lossy();
+            if let Some(inode) = target
+                .strip_prefix("socket:[")
+                .and_then(|t| t.strip_suffix(']'))
+                .and_then(|t| t.parse::<u64>().ok())
+            {
+                if wanted.contains(&inode) {
+                    owners.insert(inode, pid);
+                }
+            }
+        }
+    }
+
+    Ok(sockets
+        .into_iter()
+        .map(|(address, port, inode)| {
+            let pid = owners.get(&inode).copied();
+            let process = pid.and_then(|pid| {
+                std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                    .ok()
+                    .map(|c| c.trim().to_string())
+            });
+            let path = pid.and_then(|pid| {
+                std::fs::read_link(format!("/proc/{}/exe", pid))
+                    .ok()
+                    .map(|p| p.to_string_lossy().into_owned())
+            });
+            Listener {
+                address,
+                port,
+                pid,
+                process,
+                path,
+            }
+        })
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn listeners() -> Result<Vec<Listener>> {
+    anyhow::bail!("listening sockets can only be enumerated on Linux")
+}
+
+/// Reports listening sockets opened after it was created. Only TCP sockets
+/// are enumerated: UDP has no listening state to tell servers from clients.
+pub struct ListenerWatch {
+    options: IngressOptions,
+    /// Sockets present at startup or already seen, by address and port.
+    known: HashSet<(IpAddr, u16)>,
+    /// Sockets seen by `poll` and not reported yet.
+    opened: Vec<Listener>,
+    enabled: bool,
+}
+
+impl ListenerWatch {
+    /// Takes the baseline of listening sockets. Sockets open before the run
+    /// (runner services) are not reported.
+    pub fn new(options: &IngressOptions) -> Self {
+        let mut watch = ListenerWatch {
+            options: options.clone(),
+            known: HashSet::new(),
+            opened: Vec::new(),
+            enabled: options.action != Action::Off,
+        };
+        if !watch.enabled {
+            return watch;
+        }
+        match listeners() {
+            Ok(baseline) => watch
+                .known
+                .extend(baseline.iter().map(|l| (l.address, l.port))),
+            Err(e) => {
+                eprintln!("Warning: listening socket check disabled: {}", e);
+                watch.enabled = false;
+            }
+        }
+        watch
+    }
+
+    /// Records listening sockets opened since the last call that the
+    /// allowlist does not cover, for the next `check`. Polling often catches
+    /// sockets that close before the run ends.
+    pub fn poll(&mut self) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        for listener in listeners()? {
+            if !self.known.insert((listener.address, listener.port)) {
+                continue;
+            }
+            let process = listener
+                .process
+                .as_deref()
+                .map(|name| (name, listener.path.as_deref().unwrap_or("")));
+            if !self.options.is_allowed(listener.port, process, None) {
+                self.opened.push(listener);
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls, then prints the listening sockets recorded since the last
+    /// call. Returns whether any is a violation.
+    pub fn check(&mut self) -> Result<bool> {
+        self.poll()?;
+        let opened = std::mem::take(&mut self.opened);
+        if opened.is_empty() {
+            return Ok(false);
+        }
+        let enforced = self.options.action == Action::Fail;
+        println!(
+            "{}: {}",
+            if enforced {
+                "New listening sockets detected"
+            } else {
+                "New listening sockets (warning)"
+            },
+            opened.len()
+        );
+        for listener in &opened {
+            println!("  - [listener] {}", listener.label());
+        }
+        Ok(enforced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_net_tables() {
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 3500007F:0035 00000000:0000 0A 00000000:00000000 00:00000000 00000000   101        0 20417 1 0000000000000000 100 0 0 10 0
+   1: 00000000:115C 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1001        0 88231 1 0000000000000000 100 0 0 10 0
+   2: 0400010A:D6A2 8C72528C:01BB 01 00000000:00000000 00:00000000 00000000  1001        0 88301 1 0000000000000000 20 4 30 10 -1";
+        assert_eq!(
+            parse_proc_net(tcp),
+            vec![
+                ("127.0.0.53".parse().unwrap(), 53, 20417),
+                ("0.0.0.0".parse().unwrap(), 4444, 88231)
+            ]
+        );
+        assert_eq!(
+            parse_proc_address("00000000000000000000000001000000:1F90"),
+            Some(("::1".parse().unwrap(), 8080))
+        );
+    }
+
+    #[test]
+    fn allowlist_entries() {
+        let options = IngressOptions {
+            action: Action::Fail,
+            allow: ["8080", "10.0.0.0/8", "node:3000", "/usr/sbin/sshd"]
+                .iter()
+                .map(|a| IngressAllow::parse(a))
+                .collect(),
+        };
+        let public: IpAddr = "203.0.113.9".parse().unwrap();
+        let internal: IpAddr = "10.2.3.4".parse().unwrap();
+        assert!(options.is_allowed(8080, None, None));
+        assert!(options.is_allowed(4444, None, Some(&internal)));
+        assert!(!options.is_allowed(4444, None, Some(&public)));
+        assert!(options.is_allowed(3000, Some(("node", "/usr/bin/node")), None));
+        assert!(!options.is_allowed(3001, Some(("node", "/usr/bin/node")), None));
+        assert!(options.is_allowed(22, Some(("sshd", "/usr/sbin/sshd")), Some(&public)));
+        assert_eq!(
+            IngressAllow::parse("nc"),
+            IngressAllow::Process("nc".to_string())
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::blacklist::{parse_indicator, Indicator};
+
+/// STIX observable types whose `value` is a domain, address or URL.
+const STIX_TYPES: &[&str] = &["domain-name", "ipv4-addr", "ipv6-addr", "url"];
+
+/// STIX object types that name who or what is behind an indicator.
+const STIX_ACTOR_TYPES: &[&str] = &["threat-actor", "intrusion-set", "malware", "campaign"];
+
+/// MISP attribute types carrying a domain, address or URL.
+const MISP_TYPES: &[&str] = &[
+    "domain",
+    "hostname",
+    "ip-dst",
+    "ip-src",
+    "url",
+    "domain|ip",
+    "hostname|port",
+    "ip-dst|port",
+];
+
+/// MISP galaxies whose clusters are reported as labels.
+const MISP_ACTOR_GALAXIES: &[&str] = &["threat-actor", "mitre-intrusion-set", "malpedia"];
+
+/// Extracts indicators from a STIX 2.1 bundle or a MISP JSON export. Returns
+/// `None` when `json` is neither.
+pub fn parse(json: &Value, list: &str) -> Option<Vec<Indicator>> {
+    if json.get("type").and_then(Value::as_str) == Some("bundle") {
+        return Some(parse_stix(json, list, Utc::now()));
+    }
+    let events = misp_events(json);
+    if events.is_empty() {
+        return None;
+    }
+    Some(
+        events
+            .into_iter()
+            .flat_map(|event| parse_misp_event(event, list))
+            .collect(),
+    )
+}
+
+/// Whether a STIX pattern requires several conditions at once (`AND`,
+/// `FOLLOWEDBY`). Matching any one of its values alone would over-match.
+fn stix_pattern_is_conjunction(pattern: &str) -> bool {
+    let mut unquoted = String::new();
+    let mut quoted = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                chars.next();
+            }
+            '\'' => quoted = !quoted,
+            '[' | ']' | '(' | ')' => unquoted.push(' '),
+            _ if !quoted => unquoted.push(c),
+            _ => {}
+        }
+    }
+    unquoted
+        .split_whitespace()
+        .any(|token| token == "AND" || token == "FOLLOWEDBY")
+}
+
+/// Values compared for equality in a STIX pattern, e.g.
+/// `[domain-name:value = 'evil.com'] OR [ipv4-addr:value = '1.2.3.0/24']`.
+/// Patterns combining conditions with `AND` or `FOLLOWEDBY` are skipped.
+fn stix_pattern_values(pattern: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    if stix_pattern_is_conjunction(pattern) {
+        tracing::debug!("Skipping STIX pattern with conjunction: {}", pattern);
+        return values;
+    }
+    for kind in STIX_TYPES {
+        let needle = format!("{}:value", kind);
+        let mut rest = pattern;
+        while let Some(at) = rest.find(&needle) {
+            rest = rest[at + needle.len()..].trim_start();
+            let Some(after_eq) = rest.strip_prefix('=') else {
+                continue;
+            };
+            let Some(quoted) = after_eq.trim_start().strip_prefix('\'') else {
+                continue;
+            };
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            value.push(escaped);
+                        }
+                    }
+                    '\'' => {
+                        end = i + 1;
+                        break;
+                    }
+                    _ => value.push(c),
+                }
+            }
+            values.push(value);
+            rest = &quoted[end..];
+        }
+    }
+    values
+}
+
+fn str_field<'a>(object: &'a Value, key: &str) -> Option<&'a str> {
+    object.get(key).and_then(Value::as_str)
+}
+
+fn parse_stix(bundle: &Value, list: &str, now: DateTime<Utc>) -> Vec<Indicator> {
+    let objects: Vec<&Value> = bundle
+        .get("objects")
+        .and_then(Value::as_array)
+        .map(|a| a.iter().collect())
+        .unwrap_or_default();
+    let by_id: HashMap<&str, &Value> = objects
+        .iter()
+        .filter_map(|o| str_field(o, "id").map(|id| (id, *o)))
+        .collect();
+
+    // indicator id -> names of the actors it indicates
+    let mut actors: HashMap<&str, Vec<String>> = HashMap::new();
+    for relationship in objects
+        .iter()
+        .filter(|o| str_field(o, "type") == Some("relationship"))
+        .filter(|o| str_field(o, "relationship_type") == Some("indicates"))
+    {
+        let (Some(source), Some(target)) = (
+            str_field(relationship, "source_ref"),
+            str_field(relationship, "target_ref"),
+        ) else {
+            continue;
+        };
+        let Some(target) = by_id.get(target) else {
+            continue;
+        };
+        if STIX_ACTOR_TYPES.contains(&str_field(target, "type").unwrap_or("")) {
+            if let Some(name) = str_field(target, "name") {
+                actors.entry(source).or_default().push(name.to_string());
+            }
+        }
+    }
+
+    let mut indicators = Vec::new();
+    for object in &objects {
+        let kind = str_field(object, "type").unwrap_or("");
+        let id = str_field(object, "id");
+        let values = if kind == "indicator" {
+            if object.get("revoked").and_then(Value::as_bool) == Some(true) {
+                continue;
+            }
+            let expired = str_field(object, "valid_until")
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .is_some_and(|t| t <= now);
+            if expired || str_field(object, "pattern_type").is_some_and(|t| t != "stix") {
+                continue;
+            }
+            stix_pattern_values(str_field(object, "pattern").unwrap_or(""))
+        } else if STIX_TYPES.contains(&kind) {
+            str_field(object, "value")
+                .map(String::from)
+                .into_iter()
+                .collect()
+        } else {
+            continue;
+        };
+
+        let mut labels: Vec<String> = id
+            .and_then(|id| actors.get(id))
+            .cloned()
+            .unwrap_or_default();
+        labels.extend(
+            object
+                .get("labels")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(String::from),
+        );
+        for value in values {
+            if let Some(mut indicator) = parse_indicator(&value, list) {
+                indicator.id = id.map(String::from);
+                indicator.labels = labels.clone();
+                indicators.push(indicator);
+            }
+        }
+    }
+    indicators
+}
+
+/// The events of a MISP export: `{"Event": ...}`, `{"response": [...]}` or a
+/// bare array of either.
+fn misp_events(json: &Value) -> Vec<&Value> {
+    match json {
+        Value::Array(items) => items.iter().flat_map(misp_events).collect(),
+        Value::Object(map) => {
+            if let Some(event) = map.get("Event") {
+                vec![event]
+            } else if let Some(response) = map.get("response") {
+                misp_events(response)
+            } else {
+                Vec::new()
+            }
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Threat-actor names from MISP galaxies and `misp-galaxy:threat-actor="..."`
+/// style tags attached to an event or attribute.
+fn misp_actors(object: &Value) -> Vec<String> {
+    let mut actors = Vec::new();
+    for galaxy in object
+        .get("Galaxy")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        if !MISP_ACTOR_GALAXIES.contains(&str_field(galaxy, "type").unwrap_or("")) {
+            continue;
+        }
+        for cluster in galaxy
+            .get("GalaxyCluster")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(value) = str_field(cluster, "value") {
+                actors.push(value.to_string());
+            }
+        }
+    }
+    for tag in object
+        .get("Tag")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|t| str_field(t, "name"))
+    {
+        let Some((galaxy, value)) = tag
+            .strip_prefix("misp-galaxy:")
+            .and_then(|t| t.split_once('='))
+        else {
+            continue;
+        };
+        if MISP_ACTOR_GALAXIES.contains(&galaxy) {
+            let value = value.trim_matches('"').to_string();
+            if !actors.contains(&value) {
+                actors.push(value);
+            }
+        }
+    }
+    actors
+}
+
+fn parse_misp_event(event: &Value, list: &str) -> Vec<Indicator> {
+    let event_actors = misp_actors(event);
+    let objects = event
+        .get("Object")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten();
+    let attributes = event
+        .get("Attribute")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .chain(objects.flat_map(|o| {
+            o.get("Attribute")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+        }));
+
+    let mut indicators = Vec::new();
+    for attribute in attributes {
+        let kind = str_field(attribute, "type").unwrap_or("");
+        if !MISP_TYPES.contains(&kind) {
+            continue;
+        }
+        // Attributes not flagged for detection are context, not IOCs.
+        if attribute.get("to_ids").and_then(Value::as_bool) == Some(false) {
+            continue;
+        }
+        let Some(value) = str_field(attribute, "value") else {
+            continue;
+        };
+        let mut labels = event_actors.clone();
+        for actor in misp_actors(attribute) {
+            if !labels.contains(&actor) {
+                labels.push(actor);
+            }
+        }
+        // Composite values: both halves of `domain|ip`, the host of
+        // `hostname|port` and `ip-dst|port`.
+        let parts: Vec<&str> = match kind {
+            "domain|ip" => value.split('|').collect(),
+            _ => value.split('|').take(1).collect(),
+        };
+        for part in parts {
+            if let Some(mut indicator) = parse_indicator(part, list) {
+                indicator.id = str_field(attribute, "uuid").map(String::from);
+                indicator.labels = labels.clone();
+                indicators.push(indicator);
+            }
+        }
+    }
+    indicators
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stix_bundle() {
+        let bundle: Value = serde_json::from_str(
+            r#"{"type":"bundle","id":"bundle--1","objects":[
+                {"type":"indicator","id":"indicator--a","pattern_type":"stix",
+                 "pattern":"[domain-name:value = 'c2.example.com'] OR [url:value = 'http://198.51.100.7/gate.php']",
+                 "labels":["malicious-activity"]},
+                {"type":"indicator","id":"indicator--and","pattern_type":"stix",
+                 "pattern":"[url:value = 'http://x.example.org/a' AND ipv4-addr:value = '203.0.113.8']"},
+                {"type":"indicator","id":"indicator--seq","pattern_type":"stix",
+                 "pattern":"[domain-name:value = 'a.example.org'] FOLLOWEDBY [domain-name:value = 'b.example.org']"},
+                {"type":"indicator","id":"indicator--old","pattern_type":"stix",
+                 "pattern":"[ipv4-addr:value = '192.0.2.0/24']","valid_until":"2020-01-01T00:00:00Z"},
+                {"type":"threat-actor","id":"threat-actor--x","name":"APT-Example"},
+                {"type":"relationship","id":"relationship--r","relationship_type":"indicates",
+                 "source_ref":"indicator--a","target_ref":"threat-actor--x"},
+                {"type":"ipv6-addr","id":"ipv6-addr--s","value":"2001:db8::/32"}]}"#,
+        )
+        .unwrap();
+        let indicators = parse(&bundle, "intel").unwrap();
+        let values: Vec<&str> = indicators.iter().map(|i| i.value.as_str()).collect();
+        assert_eq!(
+            values,
+            vec!["c2.example.com", "198.51.100.7", "2001:db8::/32"]
+        );
+        assert_eq!(indicators[0].id.as_deref(), Some("indicator--a"));
+        assert!(!stix_pattern_is_conjunction(
+            "[domain-name:value = 'AND.example.com']"
+        ));
+        assert_eq!(
+            indicators[0].labels,
+            vec!["APT-Example", "malicious-activity"]
+        );
+    }
+
+    #[test]
+    fn misp_export() {
+        let export: Value = serde_json::from_str(
+            r#"{"response":[{"Event":{"id":"42","info":"Phishing wave",
+                "Tag":[{"name":"misp-galaxy:threat-actor=\"APT-Example\""},{"name":"tlp:amber"}],
+                "Attribute":[
+                  {"type":"domain|ip","value":"evil.example.com|203.0.113.5","uuid":"u1","to_ids":true},
+                  {"type":"md5","value":"d41d8cd98f00b204e9800998ecf8427e","uuid":"u2","to_ids":true},
+                  {"type":"domain","value":"context.example.com","uuid":"u3","to_ids":false}],
+                "Object":[{"name":"url","Attribute":[
+                  {"type":"url","value":"https://drop.example.net/a","uuid":"u4","to_ids":true,
+                   "Galaxy":[{"type":"malpedia","GalaxyCluster":[{"value":"ExampleLoader"}]}]}]}]}}]}"#,
+        )
+        .unwrap();
+        let indicators = parse(&export, "misp").unwrap();
+        let values: Vec<&str> = indicators.iter().map(|i| i.value.as_str()).collect();
+        assert_eq!(
+            values,
+            vec!["evil.example.com", "203.0.113.5", "drop.example.net"]
+        );
+        assert_eq!(indicators[0].id.as_deref(), Some("u1"));
+        assert_eq!(indicators[0].labels, vec!["APT-Example"]);
+        assert_eq!(indicators[2].labels, vec!["APT-Example", "ExampleLoader"]);
+        assert!(parse(&serde_json::json!({"blacklists": []}), "x").is_none());
+    }
+}
@@ -1,6 +1,27 @@
 use std::{process::Command, time::Duration};
 // Add daemon module
+mod anomaly;
+mod beacon;
+mod blacklist;
+mod categories;
 mod daemon;
+mod dns;
+mod exceptions;
+mod explain;
+mod generalize;
+mod geo;
+mod ingress;
+mod intel;
+mod lineage;
+mod merge;
+mod policy;
+mod presets;
+mod process;
+mod report;
+mod suggest;
+mod volume;
+mod whitelist;
+mod yaml;
 
 use anyhow::Result;
 use clap::{arg, ArgAction, Command as ClapCommand};
@@ -31,25 +52,25 @@ async fn main() -> Result<()> {
         Some(("scan", sub)) => {
             let seconds = *sub.get_one::<u64>("SECONDS").unwrap_or(&120);
             let until_signal = sub.get_flag("until-signal");
-            let custom_wl = sub
-                .get_one::<String>("custom-whitelist")
-                .map(|s| s.as_str());
             let output_mode = sub.get_one::<String>("output").map(|s| s.as_str());
             let check_whitelist = !sub.get_flag("no-whitelist");
             let check_blacklist = !sub.get_flag("no-blacklist");
             let check_anomaly = !sub.get_flag("no-anomaly");
             let cancel_pipeline = !sub.get_flag("no-cancel");
-            scan(
-                seconds,
-                until_signal,
-                output_mode,
-                custom_wl,
+            let emit_suggestion = sub.get_one::<String>("emit-suggestion").cloned();
+            let options = ScanOptions {
                 check_whitelist,
                 check_blacklist,
                 check_anomaly,
                 cancel_pipeline,
-            )
-            .await?;
+                emit_suggestion,
+                whitelist_source: whitelist_source(sub),
+                custom_blacklist: custom_blacklist(sub)?,
+                anomaly_options: anomaly_options(sub)?,
+                policy_options: policy_options(sub)?,
+                cmdline: cmdline_mode(sub)?,
+            };
+            scan(seconds, until_signal, output_mode, options).await?;
         }
         Some(("halt", sub)) => {
             let reason = sub.get_one::<String>("REASON").unwrap();
@@ -61,24 +82,154 @@ async fn main() -> Result<()> {
             let check_blacklist = !sub.get_flag("no-blacklist");
             let check_anomaly = !sub.get_flag("no-anomaly");
             let cancel_pipeline = !sub.get_flag("no-cancel");
-            let custom_wl = sub
-                .get_one::<String>("custom-whitelist")
-                .map(|s| s.as_str());
-            daemon::watch_daemon(
-                poll,
+            let options = daemon::WatchOptions {
                 check_whitelist,
                 check_blacklist,
                 check_anomaly,
-                cancel_pipeline,
-                custom_wl,
-            )
-            .await?;
+                cancel_on_violation: cancel_pipeline,
+                whitelist_source: whitelist_source(sub),
+                custom_blacklist: custom_blacklist(sub)?,
+                anomaly_options: anomaly_options(sub)?,
+                policy_options: policy_options(sub)?,
+                beacon_options: beacon_options(sub)?,
+                cmdline: cmdline_mode(sub)?,
+            };
+            daemon::watch_daemon(poll, options).await?;
         }
         Some(("create-whitelist", sub)) => {
             let seconds = *sub.get_one::<u64>("SECONDS").unwrap_or(&60);
             let augment = *sub.get_one::<bool>("AUGMENT").unwrap_or(&false);
             let output_path = sub.get_one::<String>("file").map(|s| s.as_str());
-            create_whitelist(seconds, augment, output_path).await?;
+            let generalize = sub.get_one::<usize>("generalize").copied();
+            let psl_path = sub
+                .get_one::<String>("public-suffix-list")
+                .map(|s| s.as_str());
+            let from_reports: Vec<&str> = sub
+                .get_many::<String>("from-report")
+                .map(|v| v.map(|s| s.as_str()).collect())
+                .unwrap_or_default();
+            let write_options = write_options(sub);
+            if from_reports.is_empty() {
+                create_whitelist(
+                    seconds,
+                    augment,
+                    output_path,
+                    generalize,
+                    psl_path,
+                    &write_options,
+                )
+                .await?;
+            } else {
+                create_whitelist_from_reports(
+                    &from_reports,
+                    augment,
+                    output_path,
+                    generalize,
+                    psl_path,
+                    &write_options,
+                )?;
+            }
+        }
+        Some(("whitelist", sub)) => match sub.subcommand() {
+            Some(("merge", m)) => {
+                let files: Vec<&str> = m
+                    .get_many::<String>("FILES")
+                    .unwrap()
+                    .map(|s| s.as_str())
+                    .collect();
+                let mode = match m.get_one::<String>("mode").map(|s| s.as_str()) {
+                    Some("intersect") => merge::MergeMode::Intersect,
+                    _ => merge::MergeMode::Union,
+                };
+                let tag_sources = m.get_flag("tag-source");
+                let output_path = m.get_one::<String>("file").map(|s| s.as_str());
+                merge_whitelists(&files, mode, tag_sources, output_path, &write_options(m))?;
+            }
+            Some(("convert", c)) => {
+                let input = c.get_one::<String>("INPUT").unwrap();
+                let output_path = c.get_one::<String>("OUTPUT").map(|s| s.as_str());
+                let to = c.get_one::<String>("to").map(|s| s.as_str());
+                convert_whitelist(input, output_path, to, &write_options(c))?;
+            }
+            _ => unreachable!("subcommand required"),
+        },
+        Some(("explain", sub)) => {
+            // Same whitelist as scan: presets, exceptions and expiry applied
+            let Some(enforced) = whitelist_source(sub).load()? else {
+                anyhow::bail!("No custom whitelist or preset to explain against");
+            };
+            enforced.print_expired();
+            let whitelists = whitelist::WhitelistsJSON::from_json(&enforced.json)?;
+            let mut subject = match sub.get_one::<String>("session") {
+                Some(session) => explain::Subject::from_session(&explain::load_session(session)?),
+                None => {
+                    let destination = sub.get_one::<String>("DESTINATION").unwrap();
+                    explain::Subject::from_tuple(destination)?
+                }
+            };
+            if let Some(protocol) = sub.get_one::<String>("protocol") {
+                subject.protocol = protocol.to_uppercase();
+            }
+            if let Some(ip) = sub.get_one::<String>("ip") {
+                subject.ip = Some(ip.parse()?);
+            }
+            if let Some(process) = sub.get_one::<String>("process") {
+                subject.process = Some(process.clone());
+            }
+            if let Some(resolution) = sub.get_one::<String>("resolution") {
+                subject.resolution = explain::Resolution::parse(resolution)?;
+            }
+            if let Some(owner) = sub.get_one::<String>("as-owner") {
+                subject.as_owner = Some(owner.clone());
+            }
+            if !explain::explain(&whitelists, &subject, sub.get_flag("all")) {
+                std::process::exit(1);
+            }
+        }
+        Some(("presets", sub)) => match sub.subcommand() {
+            Some(("list", _)) => {
+                for preset in presets::PRESETS {
+                    println!(
+                        "{:<16} {:<8} {:>3} endpoints  {}",
+                        preset.name,
+                        preset.version,
+                        preset.whitelist()?.endpoints.len(),
+                        preset.description
+                    );
+                }
+            }
+            Some(("show", s)) => {
+                let preset = presets::get(s.get_one::<String>("NAME").unwrap())?;
+                print!(
+                    "{}",
+                    preset
+                        .whitelists()?
+                        .to_canonical_json(&whitelist::WriteOptions::default())?
+                );
+            }
+            _ => unreachable!("subcommand required"),
+        },
+        Some(("allow", sub)) => {
+            let path = sub
+                .get_one::<String>("exceptions")
+                .map(|s| s.as_str())
+                .unwrap_or(exceptions::DEFAULT_EXCEPTIONS_FILE);
+            let exception = exceptions::Exception {
+                target: sub.get_one::<String>("TARGET").unwrap(),
+                port: sub.get_one::<u16>("port").copied(),
+                protocol: sub.get_one::<String>("protocol").map(|s| s.as_str()),
+                process: sub.get_one::<String>("process").map(|s| s.as_str()),
+                reason: sub.get_one::<String>("reason").unwrap(),
+                owner: sub.get_one::<String>("owner").map(|s| s.as_str()),
+                valid_for: exceptions::parse_duration(sub.get_one::<String>("for").unwrap())?,
+            };
+            let endpoint = exceptions::allow(path, &exception)?;
+            println!(
+                "Allowed {} until {} (exceptions file: {})",
+                endpoint.label(),
+                endpoint.expires.as_deref().unwrap_or("?"),
+                path
+            );
         }
         _ => {
             // Unknown command prints help
@@ -107,7 +258,197 @@ pub fn build_cli() -> ClapCommand {
                         .value_parser(clap::value_parser!(u64)),
                 )
                 .arg(
-                    arg!(--"custom-whitelist" <PATH> "Path to a custom whitelist JSON to load before scanning")
+                    arg!(--"custom-whitelist" <PATH> "Path to a custom whitelist (JSON or YAML) to load before scanning")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"strict-whitelist" "Reject the custom whitelist if a hand-added entry has no justification")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--exceptions <PATH> "Temporary exceptions file layered on the custom whitelist (default flodviddar-exceptions.json when present)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"anomaly-model" <PATH> "Load the anomaly model from PATH at start and save it back at the end")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"anomaly-level" <LEVEL> "Analyzer verdict that counts as a violation: suspicious | abnormal (default abnormal)")
+                        .required(false)
+                        .value_parser(["suspicious", "abnormal"]),
+                )
+                .arg(
+                    arg!(--"anomaly-min-count" <N> "Anomalous sessions needed before anomalies are a violation (default 1)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"anomaly-warmup" <DURATION> "Report but do not enforce anomalies in sessions started during this period (e.g. 90s, 5m)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"forbid-direct-ip" "Flag egress to destinations without Forward DNS or SNI attribution, regardless of whitelist (heuristic: reverse DNS names are only recognised when they embed the IP)")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"allow-direct-ip" <CIDR> "Network exempt from --forbid-direct-ip (internal infrastructure); repeatable")
+                        .required(false)
+                        .requires("forbid-direct-ip")
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--imds <ACTION> "Action on cloud metadata service (169.254.169.254, fd00:ec2::254) access: off | warn | fail (default warn)")
+                        .required(false)
+                        .value_parser(["off", "warn", "fail"]),
+                )
+                .arg(
+                    arg!(--"imds-allow-process" <NAME> "Process name or path allowed to reach the metadata service; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"deny-country" <CC> "Fail on egress to these ISO country codes, whatever the domain; comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"allow-country" <CC> "Only allow egress to these ISO country codes (and --allow-asn); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"deny-asn" <ASN> "Fail on egress to these AS numbers (e.g. AS64500); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"allow-asn" <ASN> "Only allow egress to these AS numbers (and --allow-country); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"egress-limit" <LIMIT> "Egress threshold <destination|process|run>.<bytes|ratio|connections>=<value>, e.g. destination.bytes=500MB; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"dns-heuristics" <ACTION> "Action on likely DNS tunneling or generated (DGA) domains among the names of connected sessions; DNS queries are not seen, so query-only tunneling is not detected: off | warn | fail (default warn)")
+                        .required(false)
+                        .value_parser(["off", "warn", "fail"]),
+                )
+                .arg(
+                    arg!(--"dns-max-label-length" <N> "Longest subdomain label considered normal (default 40)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"dns-max-entropy" <BITS> "Highest subdomain entropy in bits per character considered normal (default 4.0)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(f64)),
+                )
+                .arg(
+                    arg!(--"dns-max-subdomains" <N> "Distinct connected names (not DNS queries) under one registrable domain considered normal (default 100)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"dga-threshold" <SCORE> "DGA score from 0 to 1 at which a domain is flagged (default 0.6)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(f64)),
+                )
+                .arg(
+                    arg!(--"dns-allow" <DOMAIN> "Domain (wildcards allowed) exempt from the DNS heuristics; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--ingress <ACTION> "Action on new non-loopback listening TCP sockets and inbound sessions from outside the local network: off | warn | fail (default off)")
+                        .required(false)
+                        .value_parser(["off", "warn", "fail"]),
+                )
+                .arg(
+                    arg!(--"ingress-allow" <ENTRY> "Port, source CIDR, process, or process:port exempt from --ingress, e.g. 8080 or node:3000; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--category <SETTING> "Action per service category: <paste|tunnel|tor|mining|all>=<off|warn|fail> (default all=warn); repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"category-allow" <RULE> "Let a process reach a category, domain or CIDR: <target>=<process|*>, e.g. gist.githubusercontent.com=git; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"category-db" <PATH> "Local category database extending the bundled one (same JSON format); repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"allow-process" <GLOB> "Only these executables may open egress; name or path glob (e.g. git, docker*, /usr/bin/*); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"deny-process" <GLOB> "Executables that may never open egress (e.g. bash, sh, python*); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--cmdline <MODE> "Command lines recorded in the process lineage of egress sessions (default: redacted)")
+                        .required(false)
+                        .value_parser(["full", "redacted", "off"]),
+                )
+                .arg(
+                    arg!(--"asn-db" <PATH> "Offline IP-to-ASN/country database (iptoasn.com TSV) used for country and AS rules; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"custom-blacklist" <PATH> "Blocklist to check in addition to Flodbadd's (Flodbadd JSON, CSV, plain or hosts-file list); repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--preset <NAMES> "Comma-separated built-in presets layered under the custom whitelist (see `presets list`)")
+                        .required(false)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::PossibleValuesParser::new(presets::names())),
+                )
+                .arg(
+                    arg!(--"emit-suggestion" <PATH> "On whitelist violations, write the missing entries to PATH and a .patch against the custom whitelist")
                         .required(false)
                         .value_parser(clap::value_parser!(String)),
                 )
@@ -178,12 +519,223 @@ pub fn build_cli() -> ClapCommand {
                         .action(ArgAction::SetTrue),
                 )
                 .arg(
-                    arg!(--"custom-whitelist" <PATH> "Path to a custom whitelist JSON to load before watching")
+                    arg!(--"custom-whitelist" <PATH> "Path to a custom whitelist (JSON or YAML) to load before watching")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"strict-whitelist" "Reject the custom whitelist if a hand-added entry has no justification")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--exceptions <PATH> "Temporary exceptions file layered on the custom whitelist (default flodviddar-exceptions.json when present)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"anomaly-model" <PATH> "Load the anomaly model from PATH at start and save it back at the end")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"anomaly-level" <LEVEL> "Analyzer verdict that counts as a violation: suspicious | abnormal (default abnormal)")
+                        .required(false)
+                        .value_parser(["suspicious", "abnormal"]),
+                )
+                .arg(
+                    arg!(--"anomaly-min-count" <N> "Anomalous sessions needed before anomalies are a violation (default 1)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"anomaly-warmup" <DURATION> "Report but do not enforce anomalies in sessions started during this period (e.g. 90s, 5m)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"forbid-direct-ip" "Flag egress to destinations without Forward DNS or SNI attribution, regardless of whitelist (heuristic: reverse DNS names are only recognised when they embed the IP)")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"allow-direct-ip" <CIDR> "Network exempt from --forbid-direct-ip (internal infrastructure); repeatable")
+                        .required(false)
+                        .requires("forbid-direct-ip")
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--imds <ACTION> "Action on cloud metadata service (169.254.169.254, fd00:ec2::254) access: off | warn | fail (default warn)")
+                        .required(false)
+                        .value_parser(["off", "warn", "fail"]),
+                )
+                .arg(
+                    arg!(--"imds-allow-process" <NAME> "Process name or path allowed to reach the metadata service; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"deny-country" <CC> "Fail on egress to these ISO country codes, whatever the domain; comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"allow-country" <CC> "Only allow egress to these ISO country codes (and --allow-asn); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"deny-asn" <ASN> "Fail on egress to these AS numbers (e.g. AS64500); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"allow-asn" <ASN> "Only allow egress to these AS numbers (and --allow-country); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"egress-limit" <LIMIT> "Egress threshold <destination|process|run>.<bytes|ratio|connections>=<value>, e.g. destination.bytes=500MB; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"dns-heuristics" <ACTION> "Action on likely DNS tunneling or generated (DGA) domains among the names of connected sessions; DNS queries are not seen, so query-only tunneling is not detected: off | warn | fail (default warn)")
+                        .required(false)
+                        .value_parser(["off", "warn", "fail"]),
+                )
+                .arg(
+                    arg!(--"dns-max-label-length" <N> "Longest subdomain label considered normal (default 40)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"dns-max-entropy" <BITS> "Highest subdomain entropy in bits per character considered normal (default 4.0)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(f64)),
+                )
+                .arg(
+                    arg!(--"dns-max-subdomains" <N> "Distinct connected names (not DNS queries) under one registrable domain considered normal (default 100)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"dga-threshold" <SCORE> "DGA score from 0 to 1 at which a domain is flagged (default 0.6)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(f64)),
+                )
+                .arg(
+                    arg!(--"dns-allow" <DOMAIN> "Domain (wildcards allowed) exempt from the DNS heuristics; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--ingress <ACTION> "Action on new non-loopback listening TCP sockets and inbound sessions from outside the local network: off | warn | fail (default off)")
+                        .required(false)
+                        .value_parser(["off", "warn", "fail"]),
+                )
+                .arg(
+                    arg!(--"ingress-allow" <ENTRY> "Port, source CIDR, process, or process:port exempt from --ingress, e.g. 8080 or node:3000; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--category <SETTING> "Action per service category: <paste|tunnel|tor|mining|all>=<off|warn|fail> (default all=warn); repeatable")
                         .required(false)
+                        .action(ArgAction::Append)
                         .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"category-allow" <RULE> "Let a process reach a category, domain or CIDR: <target>=<process|*>, e.g. gist.githubusercontent.com=git; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"category-db" <PATH> "Local category database extending the bundled one (same JSON format); repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"allow-process" <GLOB> "Only these executables may open egress; name or path glob (e.g. git, docker*, /usr/bin/*); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"deny-process" <GLOB> "Executables that may never open egress (e.g. bash, sh, python*); comma-separated or repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--cmdline <MODE> "Command lines recorded in the process lineage of egress sessions (default: redacted)")
+                        .required(false)
+                        .value_parser(["full", "redacted", "off"]),
+                )
+                .arg(
+                    arg!(--"asn-db" <PATH> "Offline IP-to-ASN/country database (iptoasn.com TSV) used for country and AS rules; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--beaconing <ACTION> "Action on periodic low-jitter connections to a destination (C2 beaconing): off | warn | fail (default warn)")
+                        .required(false)
+                        .value_parser(["off", "warn", "fail"]),
+                )
+                .arg(
+                    arg!(--"beacon-min-count" <N> "Connections to a destination needed before its timing is judged (default 6)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"beacon-max-jitter" <PERCENT> "Highest jitter, in percent of the period, that counts as beaconing (default 10)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(f64)),
+                )
+                .arg(
+                    arg!(--"beacon-min-period" <DURATION> "Ignore periods shorter than this (default 10s)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"beacon-allow" <TARGET> "Domain (wildcards allowed) or IP/CIDR expected to be periodic, e.g. an NTP server; repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"custom-blacklist" <PATH> "Blocklist to check in addition to Flodbadd's (Flodbadd JSON, CSV, plain or hosts-file list); repeatable")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--preset <NAMES> "Comma-separated built-in presets layered under the custom whitelist (see `presets list`)")
+                        .required(false)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::PossibleValuesParser::new(presets::names())),
                 ),
         )
-        .subcommand(
+        .subcommand(with_write_args(
             ClapCommand::new("create-whitelist")
                 .about("Generate a custom whitelist JSON from observed traffic; optionally augment an existing custom whitelist")
                 .arg(
@@ -201,13 +753,570 @@ pub fn build_cli() -> ClapCommand {
                     arg!(--file <PATH> "Output path for the generated (or augmented) whitelist JSON")
                         .required(false)
                         .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"from-report" <FILE> "Build the whitelist from saved `scan --output report` files instead of capturing live traffic")
+                        .required(false)
+                        .num_args(1..)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--generalize <N> "Fold N or more sibling subdomains of a registrable domain into a *.domain entry")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"public-suffix-list" <PATH> "Public suffix list (publicsuffix.org format) used by --generalize instead of the built-in one")
+                        .required(false)
+                        .requires("generalize")
+                        .value_parser(clap::value_parser!(String)),
+                ),
+        ))
+        .subcommand(
+            ClapCommand::new("whitelist")
+                .about("Whitelist file utilities")
+                .subcommand_required(true)
+                .subcommand(with_write_args(
+                    ClapCommand::new("merge")
+                        .about("Merge whitelists produced by parallel jobs, deduplicating and factorizing endpoints")
+                        .arg(
+                            arg!(<FILES> "Whitelist files (JSON or YAML) to merge")
+                                .num_args(2..)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            arg!(--mode <MODE> "Keep endpoints seen in any file (union) or in every file (intersect)")
+                                .required(false)
+                                .default_value("union")
+                                .value_parser(["union", "intersect"]),
+                        )
+                        .arg(
+                            arg!(--"tag-source" "Append the source file names (jobs) to each endpoint description")
+                                .required(false)
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            arg!(--file <PATH> "Output path for the merged whitelist JSON")
+                                .required(false)
+                                .value_parser(clap::value_parser!(String)),
+                        ),
+                ))
+                .subcommand(with_write_args(
+                    ClapCommand::new("convert")
+                        .about("Convert a whitelist between JSON and commented YAML")
+                        .arg(
+                            arg!(<INPUT> "Whitelist to convert (.json, .yaml or .yml)")
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            arg!([OUTPUT] "Output file; the format follows its extension (default stdout)")
+                                .required(false)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            arg!(--to <FORMAT> "Output format (default: from OUTPUT, else the other format)")
+                                .required(false)
+                                .value_parser(["json", "yaml"]),
+                        ),
+                )),
+        )
+        .subcommand(
+            ClapCommand::new("explain")
+                .about("Explain why a session conforms to (or violates) a custom whitelist")
+                .arg(
+                    arg!([DESTINATION] "Destination as host:port, where host is a domain or an IP")
+                        .required_unless_present("session")
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--session <SESSION> "Session JSON object from a saved report (e.g. a line of `jq -c '.[]' report.json`), or a file containing one")
+                        .required(false)
+                        .conflicts_with("DESTINATION")
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"custom-whitelist" <PATH> "Path to the custom whitelist (JSON or YAML) to explain against")
+                        .required_unless_present("preset")
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"strict-whitelist" "Reject the custom whitelist if a hand-added entry has no justification")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--exceptions <PATH> "Temporary exceptions file layered on the custom whitelist (default flodviddar-exceptions.json when present)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--preset <NAMES> "Comma-separated built-in presets layered under the custom whitelist (see `presets list`)")
+                        .required(false)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::PossibleValuesParser::new(presets::names())),
+                )
+                .arg(
+                    arg!(--protocol <PROTOCOL> "Transport protocol (default TCP)")
+                        .required(false)
+                        .value_parser(["TCP", "UDP", "tcp", "udp"]),
+                )
+                .arg(
+                    arg!(--ip <IP> "Resolved destination IP when DESTINATION is a domain")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--process <NAME> "Process that opened the session")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--resolution <KIND> "How the domain was resolved")
+                        .required(false)
+                        .value_parser(["forward-dns", "sni", "reverse-dns"]),
+                )
+                .arg(
+                    arg!(--"as-owner" <OWNER> "AS owner of the destination IP (enables the CDN guard)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--all "Also show endpoints rejected on protocol/port")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("allow")
+                .about("Add a time-boxed exception layered on the custom whitelist by scan and watch")
+                .arg(
+                    arg!(<TARGET> "Domain (one wildcard label below a registrable domain allowed), IP or CIDR to allow")
+                        .required(true)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--port <PORT> "Destination port")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u16)),
+                )
+                .arg(
+                    arg!(--protocol <PROTO> "Protocol (TCP, UDP, ...)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--process <NAME> "Restrict the exception to this process")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--for <DURATION> "How long the exception lasts (e.g. 12h, 7d, 2w)")
+                        .required(true)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--reason <TEXT> "Why the exception is needed")
+                        .required(true)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--owner <OWNER> "Who is responsible for the exception")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    arg!(--exceptions <PATH> "Exceptions file to update (default flodviddar-exceptions.json)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("presets")
+                .about("List or show the built-in whitelist presets")
+                .subcommand_required(true)
+                .subcommand(ClapCommand::new("list").about("List available presets"))
+                .subcommand(
+                    ClapCommand::new("show")
+                        .about("Print a preset as whitelist JSON")
+                        .arg(
+                            arg!(<NAME> "Preset name")
+                                .required(true)
+                                .value_parser(clap::builder::PossibleValuesParser::new(presets::names())),
+                        ),
                 ),
         )
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_emit_suggestion() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "scan",
+                "--custom-whitelist",
+                "wl.json",
+                "--emit-suggestion",
+                "suggestion.json",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        assert_eq!(
+            sub.get_one::<String>("emit-suggestion").unwrap(),
+            "suggestion.json"
+        );
+    }
+
+    #[test]
+    fn parse_presets() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "scan",
+                "--preset",
+                "npm,pypi",
+                "--preset",
+                "github-actions",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        assert_eq!(
+            whitelist_source(sub).presets,
+            vec!["npm", "pypi", "github-actions"]
+        );
+        assert!(build_cli()
+            .try_get_matches_from(vec!["flodviddar", "watch", "--preset", "cobol"])
+            .is_err());
+        assert!(build_cli()
+            .try_get_matches_from(vec!["flodviddar", "presets", "show", "maven"])
+            .is_ok());
+    }
+
+    #[test]
+    fn parse_custom_blacklist() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "watch",
+                "--custom-blacklist",
+                "iocs.txt",
+                "--custom-blacklist",
+                "feed.csv",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let paths: Vec<&String> = sub
+            .get_many::<String>("custom-blacklist")
+            .unwrap()
+            .collect();
+        assert_eq!(paths, vec!["iocs.txt", "feed.csv"]);
+    }
+
+    #[test]
+    fn parse_anomaly_options() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "watch",
+                "--anomaly-model",
+                "model.json",
+                "--anomaly-level",
+                "suspicious",
+                "--anomaly-min-count",
+                "3",
+                "--anomaly-warmup",
+                "5m",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = anomaly_options(sub).unwrap();
+        assert_eq!(options.model.as_deref(), Some("model.json"));
+        assert_eq!(options.level, anomaly::AnomalyLevel::Suspicious);
+        assert_eq!(options.min_count, 3);
+        assert_eq!(options.warmup, Some(chrono::Duration::minutes(5)));
+    }
+
+    #[test]
+    fn parse_direct_ip_policy() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "scan",
+                "--forbid-direct-ip",
+                "--allow-direct-ip",
+                "10.0.0.0/8",
+                "--allow-direct-ip",
+                "fd00::/8",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = policy_options(sub).unwrap();
+        assert!(options.forbid_direct_ip);
+        assert_eq!(options.direct_ip_allow, vec!["10.0.0.0/8", "fd00::/8"]);
+        assert_eq!(options.imds, policy::Action::Warn);
+        assert!(build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "watch",
+                "--allow-direct-ip",
+                "10.0.0.0/8"
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_imds_policy() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "watch",
+                "--imds",
+                "fail",
+                "--imds-allow-process",
+                "amazon-ssm-agent",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = policy_options(sub).unwrap();
+        assert_eq!(options.imds, policy::Action::Fail);
+        assert_eq!(options.imds_allow_process, vec!["amazon-ssm-agent"]);
+    }
+
+    #[test]
+    fn parse_geo_policy() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "scan",
+                "--deny-country",
+                "ru,kp",
+                "--deny-asn",
+                "AS64500",
+                "--allow-country",
+                "DE",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = policy_options(sub).unwrap();
+        assert_eq!(options.deny_countries, vec!["RU", "KP"]);
+        assert_eq!(options.deny_asns, vec![64500]);
+        assert_eq!(options.allow_countries, vec!["DE"]);
+
+        let matches = build_cli()
+            .try_get_matches_from(vec!["flodviddar", "watch", "--deny-country", "Russia"])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        assert!(policy_options(sub).is_err());
+    }
+
+    #[test]
+    fn parse_egress_limits() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "watch",
+                "--egress-limit",
+                "destination.bytes=500MB",
+                "--egress-limit",
+                "run.connections=200",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = policy_options(sub).unwrap();
+        assert_eq!(options.volume.limits.len(), 2);
+        assert_eq!(options.volume.limits[0].max, 500_000_000.0);
+
+        let matches = build_cli()
+            .try_get_matches_from(vec!["flodviddar", "scan", "--egress-limit", "host.bytes=1"])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        assert!(policy_options(sub).is_err());
+    }
+
+    #[test]
+    fn parse_beacon_options() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "watch",
+                "--beaconing",
+                "fail",
+                "--beacon-max-jitter",
+                "5",
+                "--beacon-min-period",
+                "30s",
+                "--beacon-allow",
+                "*.pool.ntp.org",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = beacon_options(sub).unwrap();
+        assert_eq!(options.action, policy::Action::Fail);
+        assert_eq!(options.max_jitter, 0.05);
+        assert_eq!(options.min_period, chrono::Duration::seconds(30));
+        assert_eq!(options.min_count, 6);
+        assert_eq!(options.allow, vec!["*.pool.ntp.org"]);
+        assert!(build_cli()
+            .try_get_matches_from(vec!["flodviddar", "scan", "--beaconing", "fail"])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_domain_options() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "scan",
+                "--dns-heuristics",
+                "fail",
+                "--dga-threshold",
+                "0.8",
+                "--dns-allow",
+                "*.cloudfront.net",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = policy_options(sub).unwrap().domains;
+        assert_eq!(options.action, policy::Action::Fail);
+        assert_eq!(options.dga_threshold, 0.8);
+        assert_eq!(options.max_label_length, 40);
+        assert_eq!(options.allow, vec!["*.cloudfront.net"]);
+
+        let matches = build_cli()
+            .try_get_matches_from(vec!["flodviddar", "watch", "--dga-threshold", "2"])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        assert!(policy_options(sub).is_err());
+    }
+
+    #[test]
+    fn parse_ingress_policy() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "watch",
+                "--ingress",
+                "fail",
+                "--ingress-allow",
+                "8080",
+                "--ingress-allow",
+                "node:3000",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = policy_options(sub).unwrap().ingress;
+        assert_eq!(options.action, policy::Action::Fail);
+        assert_eq!(
+            options.allow,
+            vec![
+                ingress::IngressAllow::Port(8080),
+                ingress::IngressAllow::ProcessPort("node".to_string(), 3000)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_category_policy() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "scan",
+                "--category",
+                "all=fail",
+                "--category",
+                "tor=warn",
+                "--category-allow",
+                "gist.githubusercontent.com=git",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = policy_options(sub).unwrap().categories;
+        assert_eq!(options.default_action, policy::Action::Fail);
+        assert_eq!(options.actions.get("tor"), Some(&policy::Action::Warn));
+        assert_eq!(options.allow[0].process, "git");
+
+        let matches = build_cli()
+            .try_get_matches_from(vec!["flodviddar", "watch", "--category", "warez=fail"])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        assert!(policy_options(sub).is_err());
+    }
+
+    #[test]
+    fn parse_process_policy() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "watch",
+                "--allow-process",
+                "git,npm,cargo,docker*",
+                "--deny-process",
+                "bash",
+                "--deny-process",
+                "sh,python*",
+            ])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let options = policy_options(sub).unwrap().processes;
+        assert_eq!(options.allow, vec!["git", "npm", "cargo", "docker*"]);
+        assert_eq!(options.deny, vec!["bash", "sh", "python*"]);
+    }
+
+    #[test]
+    fn parse_cmdline_mode() {
+        let matches = build_cli()
+            .try_get_matches_from(vec!["flodviddar", "scan", "60", "--cmdline", "full"])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        assert_eq!(cmdline_mode(sub).unwrap(), lineage::CmdlineMode::Full);
+        let matches = build_cli()
+            .try_get_matches_from(vec!["flodviddar", "watch"])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        assert_eq!(cmdline_mode(sub).unwrap(), lineage::CmdlineMode::Redacted);
+        assert!(build_cli()
+            .try_get_matches_from(vec!["flodviddar", "watch", "--cmdline", "some"])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_allow() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "allow",
+                "pypi.org",
+                "--port",
+                "443",
+                "--protocol",
+                "tcp",
+                "--for",
+                "7d",
+                "--reason",
+                "New dependency",
+            ])
+            .unwrap();
+        let (sc, sub) = matches.subcommand().unwrap();
+        assert_eq!(sc, "allow");
+        assert_eq!(sub.get_one::<u16>("port"), Some(&443));
+        assert_eq!(sub.get_one::<String>("for").unwrap(), "7d");
+        assert!(build_cli()
+            .try_get_matches_from(vec!["flodviddar", "allow", "pypi.org", "--for", "7d"])
+            .is_err());
+
+        let matches = build_cli()
+            .try_get_matches_from(vec!["flodviddar", "watch", "--exceptions", "exc.yaml"])
+            .unwrap();
+        let (_, sub) = matches.subcommand().unwrap();
+        let source = whitelist_source(sub);
+        assert_eq!(source.exceptions.as_deref(), Some("exc.yaml"));
+        assert_eq!(source.path, None);
+    }
 
     #[test]
     fn parse_scan_defaults() {
@@ -243,6 +1352,23 @@ mod tests {
         assert!(subm.get_flag("no-anomaly"));
     }
 
+    #[test]
+    fn parse_strict_whitelist() {
+        for sc in ["scan", "watch"] {
+            let matches = build_cli()
+                .try_get_matches_from(vec![
+                    "flodviddar",
+                    sc,
+                    "--custom-whitelist",
+                    "wl.yaml",
+                    "--strict-whitelist",
+                ])
+                .expect("parse");
+            let (_, subm) = matches.subcommand().unwrap();
+            assert!(subm.get_flag("strict-whitelist"));
+        }
+    }
+
     #[test]
     fn parse_watch_disable_cancel() {
         let matches = build_cli()
@@ -253,31 +1379,218 @@ mod tests {
         assert!(subm.get_flag("no-cancel"));
         assert!(subm.get_flag("no-blacklist"));
     }
+
+    #[test]
+    fn parse_create_whitelist_from_reports() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "create-whitelist",
+                "--from-report",
+                "linux.json",
+                "macos.json",
+                "--file",
+                "whitelist.json",
+            ])
+            .expect("parse");
+        let (_, subm) = matches.subcommand().unwrap();
+        let reports: Vec<&String> = subm.get_many::<String>("from-report").unwrap().collect();
+        assert_eq!(reports, vec!["linux.json", "macos.json"]);
+    }
+
+    #[test]
+    fn parse_whitelist_merge() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "whitelist",
+                "merge",
+                "a.json",
+                "b.json",
+                "--mode",
+                "intersect",
+                "--tag-source",
+            ])
+            .expect("parse");
+        let (_, wl) = matches.subcommand().unwrap();
+        let (sc, subm) = wl.subcommand().unwrap();
+        assert_eq!(sc, "merge");
+        assert_eq!(subm.get_many::<String>("FILES").unwrap().count(), 2);
+        assert_eq!(
+            subm.get_one::<String>("mode").map(|s| s.as_str()),
+            Some("intersect")
+        );
+        assert!(subm.get_flag("tag-source"));
+    }
+
+    #[test]
+    fn parse_whitelist_convert() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "whitelist",
+                "convert",
+                "whitelist.json",
+                "whitelist.yaml",
+            ])
+            .expect("parse");
+        let (_, wl) = matches.subcommand().unwrap();
+        let (sc, subm) = wl.subcommand().unwrap();
+        assert_eq!(sc, "convert");
+        assert_eq!(
+            subm.get_one::<String>("OUTPUT").map(|s| s.as_str()),
+            Some("whitelist.yaml")
+        );
+    }
+
+    #[test]
+    fn parse_write_options() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "create-whitelist",
+                "60",
+                "--omit-null",
+                "--no-date",
+            ])
+            .expect("parse");
+        let (_, subm) = matches.subcommand().unwrap();
+        let options = write_options(subm);
+        assert!(options.omit_null);
+        assert_eq!(options.date.as_deref(), Some(""));
+
+        assert!(build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "create-whitelist",
+                "--no-date",
+                "--date",
+                "2025-01-01"
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_explain_tuple_or_session() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "explain",
+                "api.github.com:443",
+                "--custom-whitelist",
+                "wl.json",
+                "--resolution",
+                "sni",
+            ])
+            .expect("parse");
+        let (sc, subm) = matches.subcommand().unwrap();
+        assert_eq!(sc, "explain");
+        assert_eq!(
+            subm.get_one::<String>("DESTINATION").map(|s| s.as_str()),
+            Some("api.github.com:443")
+        );
+
+        assert!(build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "explain",
+                "--custom-whitelist",
+                "wl.json"
+            ])
+            .is_err());
+        assert!(build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "explain",
+                "--session",
+                "{}",
+                "--custom-whitelist",
+                "wl.json",
+            ])
+            .is_ok());
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "flodviddar",
+                "explain",
+                "pypi.org:443",
+                "--preset",
+                "pypi",
+                "--exceptions",
+                "exc.json",
+            ])
+            .expect("parse");
+        let (_, subm) = matches.subcommand().unwrap();
+        let source = whitelist_source(subm);
+        assert_eq!(source.presets, vec!["pypi"]);
+        assert_eq!(source.exceptions.as_deref(), Some("exc.json"));
+        assert!(build_cli()
+            .try_get_matches_from(vec!["flodviddar", "explain", "pypi.org:443"])
+            .is_err());
+    }
 }
 
-async fn scan(
-    seconds: u64,
-    until_signal: bool,
-    output_mode: Option<&str>,
-    custom_wl: Option<&str>,
+/// What `scan` checks and how it reacts to a violation.
+struct ScanOptions {
     check_whitelist: bool,
     check_blacklist: bool,
     check_anomaly: bool,
+    /// Halt the CI pipeline and exit 1 when a violation is found.
     cancel_pipeline: bool,
+    /// Where to write the whitelist suggestion for the exceptions found.
+    emit_suggestion: Option<String>,
+    whitelist_source: whitelist::WhitelistSource,
+    custom_blacklist: blacklist::CustomBlacklist,
+    anomaly_options: anomaly::AnomalyOptions,
+    policy_options: policy::PolicyOptions,
+    cmdline: lineage::CmdlineMode,
+}
+
+async fn scan(
+    seconds: u64,
+    until_signal: bool,
+    output_mode: Option<&str>,
+    options: ScanOptions,
 ) -> Result<()> {
+    let ScanOptions {
+        check_whitelist,
+        check_blacklist,
+        check_anomaly,
+        cancel_pipeline,
+        emit_suggestion,
+        whitelist_source,
+        custom_blacklist,
+        anomaly_options,
+        policy_options,
+        cmdline,
+    } = options;
     // Discover network interfaces
     let interfaces = get_valid_network_interfaces();
     let capture = FlodbaddCapture::new();
-    if let Some(path) = custom_wl {
-        if std::path::Path::new(path).exists() {
-            let json = std::fs::read_to_string(path)?;
-            capture.set_custom_whitelists(&json).await;
-        } else {
-            eprintln!("Custom whitelist file '{}' not found, ignoring", path);
-        }
+    let mut budgeted = Vec::new();
+    if let Some(enforced) = whitelist_source.load()? {
+        enforced.print_expired();
+        capture.set_custom_whitelists(&enforced.json).await;
+        budgeted = enforced.budgeted;
     }
+    if let Some(json) = custom_blacklist.engine_json() {
+        capture.set_custom_blacklists(&json).await?;
+    }
+
+    // Start the analyzer before capture so a saved model is replayed first
+    let analyzer = SessionAnalyzer::new();
+    analyzer.start().await;
+    let mut model = anomaly_options.warm_analyzer(&analyzer).await?;
+
+    // Listening sockets open before the run are not reported
+    let mut listeners = ingress::ListenerWatch::new(&policy_options.ingress);
+
+    // Process lineage and listening sockets are read while the run is live,
+    // before processes exit and sockets close
+    let mut lineage = lineage::LineageRecorder::new(cmdline);
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
 
     // Start capture
+    let run_start = chrono::Utc::now();
     capture.start(&interfaces).await?;
     if until_signal {
         tracing::info!("Started capture; waiting for Ctrl-C/SIGTERM to stop");
@@ -286,9 +1599,15 @@ async fn scan(
         {
             use tokio::signal::unix::{signal, SignalKind};
             let mut sigterm = signal(SignalKind::terminate())?;
-            tokio::select! {
-                _ = tokio::signal::ctrl_c() => {},
-                _ = sigterm.recv() => {},
+            loop {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => break,
+                    _ = sigterm.recv() => break,
+                    _ = ticker.tick() => {
+                        lineage.record(&capture.get_current_sessions(false).await);
+                        listeners.poll()?;
+                    }
+                }
             }
         }
         #[cfg(target_os = "windows")]
@@ -298,24 +1617,50 @@ async fn scan(
         tracing::info!("Signal received, stopping capture");
     } else {
         tracing::info!("Started capture for {seconds} seconds");
-        sleep(Duration::from_secs(seconds)).await;
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(seconds);
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => break,
+                _ = ticker.tick() => {
+                    lineage.record(&capture.get_current_sessions(false).await);
+                    listeners.poll()?;
+                }
+            }
+        }
+    }
+
+    // Destinations still resolving get a grace period before they are judged
+    if capture
+        .get_sessions(false)
+        .await
+        .iter()
+        .any(|s| policy_options.awaits_resolution(s))
+    {
+        sleep(policy::RESOLUTION_GRACE).await;
     }
 
     // Stop capture and analyze sessions
     let sessions = capture.get_sessions(false).await;
     tracing::info!("Captured {} sessions", sessions.len());
+    lineage.record(&sessions);
 
     let blacklisted = capture.get_blacklisted_sessions(false).await;
 
     // Run anomaly detection using SessionAnalyzer
-    let analyzer = SessionAnalyzer::new();
-    analyzer.start().await;
     let mut session_clone = sessions.clone();
     analyzer.analyze_sessions(&mut session_clone).await;
-    let anomalous = analyzer.get_anomalous_sessions().await;
+    let anomalies = anomaly_options.classify(
+        &model,
+        &session_clone,
+        analyzer.get_anomalous_sessions().await,
+        run_start,
+    );
+    anomaly_options.learn(&mut model, &session_clone, &anomalies);
+    anomaly_options.save_model(&model)?;
 
     let mut violations = false;
     let mut violating_sessions: Vec<flodbadd::sessions::SessionInfo> = Vec::new();
+    let mut annotations = report::Annotations::default();
 
     if check_whitelist {
         let conform = capture.get_whitelist_conformance().await;
@@ -323,28 +1668,116 @@ async fn scan(
             let exceptions = capture.get_whitelist_exceptions(false).await;
             println!("Whitelist exceptions detected: {}", exceptions.len());
             violations = true;
+            if let Some(path) = &emit_suggestion {
+                let generated =
+                    flodbadd::whitelists::WhitelistsJSON::new_from_sessions(&exceptions);
+                let mut generated =
+                    whitelist::WhitelistsJSON::from_json(&serde_json::to_string(&generated)?)?;
+                generated.mark_generated();
+                suggest::emit(path, whitelist_source.path.as_deref(), &generated)?;
+            }
             violating_sessions.extend(exceptions);
         }
     }
 
     if check_blacklist && !blacklisted.is_empty() {
         println!("Blacklisted sessions detected: {}", blacklisted.len());
+        let hits = custom_blacklist.range_hits(&blacklisted);
+        blacklist::print_hits(&hits);
+        blacklist::annotate(&hits, &mut annotations)?;
         violations = true;
         violating_sessions.extend(blacklisted.clone());
     }
 
-    if check_anomaly && !anomalous.is_empty() {
-        println!("Anomalous sessions detected: {}", anomalous.len());
+    if check_blacklist && !custom_blacklist.is_empty() {
+        let hits = custom_blacklist.check(&sessions);
+        if !hits.is_empty() {
+            println!("Custom blacklist hits detected: {}", hits.len());
+            blacklist::print_hits(&hits);
+            blacklist::annotate(&hits, &mut annotations)?;
+            violations = true;
+            violating_sessions.extend(hits.into_iter().map(|hit| hit.session));
+        }
+    }
+
+    let mut policy_violations = policy_options.check(&sessions);
+    policy_violations.extend(
+        sessions
+            .iter()
+            .filter_map(|s| policy_options.unresolved_violation(s)),
+    );
+    let policy_violations = policy::report(policy_violations);
+    if !policy_violations.is_empty() {
+        violations = true;
+        violating_sessions.extend(policy_violations);
+    }
+
+    let mut domains = dns::DomainDetector::new(policy_options.domains.clone());
+    domains.observe(&sessions);
+    let domain_findings = policy::report(domains.detect());
+    if !domain_findings.is_empty() {
+        violations = true;
+        violating_sessions.extend(domain_findings);
+    }
+
+    if listeners.check()? {
+        violations = true;
+    }
+
+    let volume_violations = volume::report(policy_options.volume.check(&sessions, &budgeted));
+    if !volume_violations.is_empty() {
+        violations = true;
+        violating_sessions.extend(volume_violations);
+    }
+
+    if check_anomaly && !anomalies.recorded.is_empty() {
+        println!(
+            "Anomalous sessions recorded (warm-up or below threshold, not enforced): {}",
+            anomalies.recorded.len()
+        );
+    }
+
+    if check_anomaly {
+        let baseline = model.baseline(&session_clone, &anomalies);
+        anomaly::print_explanations(&anomaly_options, &anomalies, &baseline);
+        anomaly::annotate(&anomaly_options, &anomalies, &baseline, &mut annotations)?;
+    }
+
+    if check_anomaly && !anomalies.enforced.is_empty() {
+        println!("Anomalous sessions detected: {}", anomalies.enforced.len());
         violations = true;
-        violating_sessions.extend(anomalous.clone());
+        violating_sessions.extend(anomalies.enforced.clone());
     }
 
+    // Build the requested output first, so a run that halts still writes it
+    let output = match output_mode {
+        Some("whitelist") => {
+            let mut generated =
+                whitelist::WhitelistsJSON::from_json(&capture.create_custom_whitelists().await?)?;
+            generated.mark_generated();
+            Some(serde_json::to_string_pretty(&generated)?)
+        }
+        Some("report") => {
+            // Dump sessions as JSON, with blacklist hits, anomaly
+            // explanations and the lineage of their processes
+            lineage.annotate(&sessions, &mut annotations)?;
+            Some(serde_json::to_string_pretty(
+                &annotations.apply(&sessions)?,
+            )?)
+        }
+        _ => None,
+    };
+
     if cancel_pipeline && violations {
         // Print violating sessions report
         println!("\n=== Violating Sessions ===");
         for line in format_sessions_log(&violating_sessions) {
             println!("{}", line);
         }
+        lineage::print(&lineage, &violating_sessions);
+        if let Some(output) = &output {
+            println!("{}", output);
+        }
 
         println!("\nPolicy violations detected. Halting CI pipeline...");
         halt_ci_pipeline("Flodviddar detected policy violations")?;
@@ -356,27 +1789,24 @@ async fn scan(
     for line in format_sessions_log(&sessions) {
         println!("{}", line);
     }
+    lineage::print(&lineage, &sessions);
 
     // Handle requested output
-    if let Some(mode) = output_mode {
-        match mode {
-            "whitelist" => {
-                let json = capture.create_custom_whitelists().await?;
-                println!("{}", json);
-            }
-            "report" => {
-                // Dump sessions as JSON
-                let json = serde_json::to_string_pretty(&sessions)?;
-                println!("{}", json);
-            }
-            _ => {}
-        }
+    if let Some(output) = output {
+        println!("{}", output);
     }
 
     Ok(())
 }
 
-async fn create_whitelist(seconds: u64, augment: bool, output_path: Option<&str>) -> Result<()> {
+async fn create_whitelist(
+    seconds: u64,
+    augment: bool,
+    output_path: Option<&str>,
+    generalize: Option<usize>,
+    psl_path: Option<&str>,
+    write_options: &whitelist::WriteOptions,
+) -> Result<()> {
     let interfaces = get_valid_network_interfaces();
     let capture = FlodbaddCapture::new();
     capture.start(&interfaces).await?;
@@ -388,7 +1818,7 @@ async fn create_whitelist(seconds: u64, augment: bool, output_path: Option<&str>
     if augment {
         if let Some(path) = output_path {
             if std::path::Path::new(path).exists() {
-                let existing = std::fs::read_to_string(path)?;
+                let existing = whitelist::load_engine_json(path)?;
                 capture.set_custom_whitelists(&existing).await;
             }
         }
@@ -401,16 +1831,416 @@ async fn create_whitelist(seconds: u64, augment: bool, output_path: Option<&str>
         capture.create_custom_whitelists().await?
     };
 
-    if let Some(path) = output_path {
-        std::fs::write(path, &json)?;
-        println!("Whitelist written to {}", path);
+    let mut whitelists = whitelist::WhitelistsJSON::from_json(&json)?;
+    // Existing entries get their own marker back from the file.
+    whitelists.mark_generated();
+    if augment {
+        if let Some(path) = output_path.filter(|p| std::path::Path::new(p).exists()) {
+            whitelists.restore_metadata(&whitelist::WhitelistsJSON::load(path)?);
+        }
+    }
+    if let Some(min_siblings) = generalize {
+        generalize_whitelist(&mut whitelists, min_siblings, psl_path)?;
+    }
+
+    write_whitelist(&whitelists, output_path, write_options)
+}
+
+/// Builds (or augments) a custom whitelist from the sessions of one or more
+/// saved reports, e.g. archived CI artifacts of different matrix jobs.
+fn create_whitelist_from_reports(
+    reports: &[&str],
+    augment: bool,
+    output_path: Option<&str>,
+    generalize: Option<usize>,
+    psl_path: Option<&str>,
+    write_options: &whitelist::WriteOptions,
+) -> Result<()> {
+    let mut sessions = Vec::new();
+    for path in reports {
+        let loaded = report::load_sessions(path)?;
+        tracing::info!("Loaded {} sessions from {}", loaded.len(), path);
+        sessions.extend(loaded);
+    }
+
+    let generated = flodbadd::whitelists::WhitelistsJSON::new_from_sessions(&sessions);
+    let mut whitelists = whitelist::WhitelistsJSON::from_json(&serde_json::to_string(&generated)?)?;
+    whitelists.mark_generated();
+
+    if augment {
+        if let Some(path) = output_path {
+            if std::path::Path::new(path).exists() {
+                let mut existing = whitelist::WhitelistsJSON::load(path)?;
+                let added = existing.augment(&whitelists);
+                println!("Added {} endpoints to {}", added, path);
+                whitelists = existing;
+            }
+        }
+    }
+
+    if let Some(min_siblings) = generalize {
+        generalize_whitelist(&mut whitelists, min_siblings, psl_path)?;
+    }
+
+    write_whitelist(&whitelists, output_path, write_options)
+}
+
+/// Merges whitelist files, labelling each input with its file stem so that
+/// `--tag-source` records which matrix job contributed an endpoint.
+fn merge_whitelists(
+    files: &[&str],
+    mode: merge::MergeMode,
+    tag_sources: bool,
+    output_path: Option<&str>,
+    write_options: &whitelist::WriteOptions,
+) -> Result<()> {
+    let mut inputs = Vec::new();
+    for path in files {
+        let label = std::path::Path::new(path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string());
+        inputs.push((label, whitelist::WhitelistsJSON::load(path)?));
+    }
+    let merged = merge::merge(&inputs, mode, tag_sources);
+    let count: usize = merged.whitelists.iter().map(|w| w.endpoints.len()).sum();
+    eprintln!("Merged {} files into {} endpoints", files.len(), count);
+    write_whitelist(&merged, output_path, write_options)
+}
+
+/// Writes a whitelist in canonical form so that re-runs on the same traffic
+/// produce byte-identical files. Paths ending in `.yaml`/`.yml` are written as
+/// YAML, keeping the comments of the file being replaced.
+fn write_whitelist(
+    whitelists: &whitelist::WhitelistsJSON,
+    output_path: Option<&str>,
+    write_options: &whitelist::WriteOptions,
+) -> Result<()> {
+    let Some(path) = output_path else {
+        print!("{}", whitelists.to_canonical_json(write_options)?);
+        return Ok(());
+    };
+
+    let contents = if yaml::is_yaml_path(path) {
+        let comments = if std::path::Path::new(path).exists() {
+            yaml::load(path).map(|(_, c)| c).unwrap_or_default()
+        } else {
+            yaml::Comments::default()
+        };
+        yaml::to_yaml(whitelists, &comments, write_options)
+    } else {
+        whitelists.to_canonical_json(write_options)?
+    };
+    std::fs::write(path, contents)?;
+    println!("Whitelist written to {}", path);
+
+    Ok(())
+}
+
+/// Converts a whitelist between JSON and YAML. YAML comments are carried over
+/// when converting YAML to YAML; JSON has no place for them.
+fn convert_whitelist(
+    input: &str,
+    output_path: Option<&str>,
+    to: Option<&str>,
+    write_options: &whitelist::WriteOptions,
+) -> Result<()> {
+    let (whitelists, comments) = if yaml::is_yaml_path(input) {
+        yaml::load(input)?
+    } else {
+        (
+            whitelist::WhitelistsJSON::load(input)?,
+            yaml::Comments::default(),
+        )
+    };
+    let to_yaml = match (to, output_path) {
+        (Some(format), _) => format == "yaml",
+        (None, Some(path)) => yaml::is_yaml_path(path),
+        (None, None) => !yaml::is_yaml_path(input),
+    };
+    let contents = if to_yaml {
+        yaml::to_yaml(&whitelists, &comments, write_options)
     } else {
-        println!("{}", json);
+        whitelists.to_canonical_json(write_options)?
+    };
+    match output_path {
+        Some(path) => {
+            std::fs::write(path, contents)?;
+            println!("Whitelist written to {}", path);
+        }
+        None => print!("{}", contents),
     }
+    Ok(())
+}
 
+/// Applies the wildcard generalization pass to the custom whitelist and
+/// reports each proposed wildcard on stderr.
+fn generalize_whitelist(
+    whitelists: &mut whitelist::WhitelistsJSON,
+    min_siblings: usize,
+    psl_path: Option<&str>,
+) -> Result<()> {
+    let psl = match psl_path {
+        Some(path) => generalize::PublicSuffixList::load(path)?,
+        None => generalize::PublicSuffixList::builtin(),
+    };
+    for info in whitelists
+        .whitelists
+        .iter_mut()
+        .filter(|w| w.name == whitelist::CUSTOM_WHITELIST_NAME)
+    {
+        for g in generalize::generalize(info, &psl, min_siblings) {
+            eprintln!(
+                "Generalized {} subdomains into {}: {}",
+                g.replaced.len(),
+                g.wildcard,
+                g.replaced.join(", ")
+            );
+        }
+    }
     Ok(())
 }
 
+/// Reads the `--omit-null`, `--no-date` and `--date` flags shared by the
+/// commands that write whitelist files.
+fn write_options(sub: &clap::ArgMatches) -> whitelist::WriteOptions {
+    whitelist::WriteOptions {
+        omit_null: sub.get_flag("omit-null"),
+        date: if sub.get_flag("no-date") {
+            Some(String::new())
+        } else {
+            sub.get_one::<String>("date").cloned()
+        },
+    }
+}
+
+/// Reads the custom whitelist, exceptions and strictness flags shared by
+/// `scan` and `watch`. The default exceptions file is used when present.
+fn whitelist_source(sub: &clap::ArgMatches) -> whitelist::WhitelistSource {
+    let exceptions = sub.get_one::<String>("exceptions").cloned().or_else(|| {
+        std::path::Path::new(exceptions::DEFAULT_EXCEPTIONS_FILE)
+            .exists()
+            .then(|| exceptions::DEFAULT_EXCEPTIONS_FILE.to_string())
+    });
+    whitelist::WhitelistSource {
+        path: sub.get_one::<String>("custom-whitelist").cloned(),
+        exceptions,
+        presets: sub
+            .get_many::<String>("preset")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default(),
+        strict: sub.get_flag("strict-whitelist"),
+    }
+}
+
+/// Reads the anomaly model, level, threshold and warm-up flags shared by
+/// `scan` and `watch`.
+fn anomaly_options(sub: &clap::ArgMatches) -> Result<anomaly::AnomalyOptions> {
+    Ok(anomaly::AnomalyOptions {
+        model: sub.get_one::<String>("anomaly-model").cloned(),
+        level: match sub.get_one::<String>("anomaly-level").map(|s| s.as_str()) {
+            Some("suspicious") => anomaly::AnomalyLevel::Suspicious,
+            _ => anomaly::AnomalyLevel::Abnormal,
+        },
+        min_count: sub
+            .get_one::<usize>("anomaly-min-count")
+            .copied()
+            .unwrap_or(1),
+        warmup: sub
+            .get_one::<String>("anomaly-warmup")
+            .map(|d| exceptions::parse_duration(d))
+            .transpose()?,
+    })
+}
+
+/// Reads the `--cmdline` flag shared by `scan` and `watch`.
+fn cmdline_mode(sub: &clap::ArgMatches) -> Result<lineage::CmdlineMode> {
+    match sub.get_one::<String>("cmdline") {
+        Some(mode) => lineage::CmdlineMode::parse(mode),
+        None => Ok(lineage::CmdlineMode::default()),
+    }
+}
+
+/// Reads the beaconing detection flags of `watch`.
+fn beacon_options(sub: &clap::ArgMatches) -> Result<beacon::BeaconOptions> {
+    let defaults = beacon::BeaconOptions::default();
+    Ok(beacon::BeaconOptions {
+        action: match sub.get_one::<String>("beaconing") {
+            Some(action) => policy::Action::parse(action)?,
+            None => defaults.action,
+        },
+        min_count: sub
+            .get_one::<usize>("beacon-min-count")
+            .copied()
+            .unwrap_or(defaults.min_count)
+            .max(3),
+        max_jitter: sub
+            .get_one::<f64>("beacon-max-jitter")
+            .map(|p| p / 100.0)
+            .unwrap_or(defaults.max_jitter),
+        min_period: match sub.get_one::<String>("beacon-min-period") {
+            Some(d) => exceptions::parse_duration(d)?,
+            None => defaults.min_period,
+        },
+        allow: sub
+            .get_many::<String>("beacon-allow")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default(),
+    })
+}
+
+/// Reads the DNS tunneling and DGA flags shared by `scan` and `watch`.
+fn domain_options(sub: &clap::ArgMatches) -> Result<dns::DomainOptions> {
+    let defaults = dns::DomainOptions::default();
+    let dga_threshold = sub
+        .get_one::<f64>("dga-threshold")
+        .copied()
+        .unwrap_or(defaults.dga_threshold);
+    if !(0.0..=1.0).contains(&dga_threshold) {
+        anyhow::bail!("--dga-threshold must be between 0 and 1");
+    }
+    Ok(dns::DomainOptions {
+        action: match sub.get_one::<String>("dns-heuristics") {
+            Some(action) => policy::Action::parse(action)?,
+            None => defaults.action,
+        },
+        max_label_length: sub
+            .get_one::<usize>("dns-max-label-length")
+            .copied()
+            .unwrap_or(defaults.max_label_length),
+        max_name_length: defaults.max_name_length,
+        max_entropy: sub
+            .get_one::<f64>("dns-max-entropy")
+            .copied()
+            .unwrap_or(defaults.max_entropy),
+        max_subdomains: sub
+            .get_one::<usize>("dns-max-subdomains")
+            .copied()
+            .unwrap_or(defaults.max_subdomains),
+        dga_threshold,
+        allow: sub
+            .get_many::<String>("dns-allow")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default(),
+    })
+}
+
+/// Reads the service category flags shared by `scan` and `watch`.
+fn category_options(sub: &clap::ArgMatches) -> Result<categories::CategoryOptions> {
+    let strings = |id: &str| -> Vec<String> {
+        sub.get_many::<String>(id)
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default()
+    };
+    let mut options = categories::CategoryOptions {
+        database: categories::CategoryDatabase::load(&strings("category-db"))?,
+        ..Default::default()
+    };
+    for setting in strings("category") {
+        options.set(&setting)?;
+    }
+    options.allow = strings("category-allow")
+        .iter()
+        .map(|a| categories::CategoryAllow::parse(a))
+        .collect::<Result<_>>()?;
+    Ok(options)
+}
+
+/// Reads the egress policy flags shared by `scan` and `watch`.
+fn policy_options(sub: &clap::ArgMatches) -> Result<policy::PolicyOptions> {
+    let strings = |id: &str| -> Vec<String> {
+        sub.get_many::<String>(id)
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default()
+    };
+    let direct_ip_allow = strings("allow-direct-ip");
+    for network in &direct_ip_allow {
+        policy::validate_network(network)?;
+    }
+    Ok(policy::PolicyOptions {
+        forbid_direct_ip: sub.get_flag("forbid-direct-ip"),
+        direct_ip_allow,
+        imds: policy::Action::parse(
+            sub.get_one::<String>("imds")
+                .map(|s| s.as_str())
+                .unwrap_or("warn"),
+        )?,
+        imds_allow_process: strings("imds-allow-process"),
+        deny_countries: strings("deny-country")
+            .iter()
+            .map(|c| policy::parse_country(c))
+            .collect::<Result<_>>()?,
+        allow_countries: strings("allow-country")
+            .iter()
+            .map(|c| policy::parse_country(c))
+            .collect::<Result<_>>()?,
+        deny_asns: strings("deny-asn")
+            .iter()
+            .map(|a| policy::parse_asn(a))
+            .collect::<Result<_>>()?,
+        allow_asns: strings("allow-asn")
+            .iter()
+            .map(|a| policy::parse_asn(a))
+            .collect::<Result<_>>()?,
+        asn_database: geo::AsnDatabase::load(&strings("asn-db"))?,
+        domains: domain_options(sub)?,
+        categories: category_options(sub)?,
+        processes: process::ProcessPolicy {
+            allow: strings("allow-process"),
+            deny: strings("deny-process"),
+        },
+        ingress: ingress::IngressOptions {
+            action: policy::Action::parse(
+                sub.get_one::<String>("ingress")
+                    .map(|s| s.as_str())
+                    .unwrap_or("off"),
+            )?,
+            allow: strings("ingress-allow")
+                .iter()
+                .map(|a| ingress::IngressAllow::parse(a))
+                .collect(),
+        },
+        volume: volume::VolumeLimits {
+            limits: strings("egress-limit")
+                .iter()
+                .map(|l| volume::parse_limit(l))
+                .collect::<Result<_>>()?,
+        },
+    })
+}
+
+/// Loads the `--custom-blacklist` files given to `scan` or `watch`, unless
+/// blacklist checking is off.
+fn custom_blacklist(sub: &clap::ArgMatches) -> Result<blacklist::CustomBlacklist> {
+    if sub.get_flag("no-blacklist") {
+        return Ok(blacklist::CustomBlacklist::default());
+    }
+    let paths: Vec<String> = sub
+        .get_many::<String>("custom-blacklist")
+        .map(|v| v.cloned().collect())
+        .unwrap_or_default();
+    blacklist::CustomBlacklist::load(&paths)
+}
+
+fn with_write_args(cmd: ClapCommand) -> ClapCommand {
+    cmd.arg(
+        arg!(--"omit-null" "Leave out null fields in the written whitelist")
+            .required(false)
+            .action(ArgAction::SetTrue),
+    )
+    .arg(
+        arg!(--"no-date" "Write an empty date so identical traffic yields identical files")
+            .required(false)
+            .action(ArgAction::SetTrue),
+    )
+    .arg(
+        arg!(--date <DATE> "Write this fixed date instead of the generation date")
+            .required(false)
+            .conflicts_with("no-date")
+            .value_parser(clap::value_parser!(String)),
+    )
+}
+
 /// Detects GitHub Actions or GitLab CI environment and attempts to cancel the current pipeline
 /// First checks for external cancellation script, then falls back to built-in logic
 fn halt_ci_pipeline(reason: &str) -> Result<()> {
@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::whitelist::{EndpointFingerprint, WhitelistEndpoint, WhitelistInfo, WhitelistsJSON};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeMode {
+    /// Keep every endpoint seen in any input.
+    Union,
+    /// Keep only endpoints seen in every input.
+    Intersect,
+}
+
+/// An endpoint identity with its IPs factored out. Domain endpoints are keyed
+/// without their IP so that CDN rotation across jobs merges into one IP set;
+/// IP-only endpoints keep the IP as their identity.
+struct Factored {
+    template: WhitelistEndpoint,
+    /// IPs seen per input. `None` means the endpoint was seen without an IP
+    /// restriction, which subsumes any specific IP.
+    ips: BTreeMap<usize, BTreeSet<Option<String>>>,
+}
+
+impl Factored {
+    /// The merged IP set: every IP seen by any input for a union, only the
+    /// IPs all inputs agree on for an intersection. An empty set means the
+    /// inputs share no IP for this endpoint.
+    fn merged_ips(&self, mode: MergeMode) -> BTreeSet<Option<String>> {
+        if mode == MergeMode::Union {
+            let all: BTreeSet<Option<String>> = self.ips.values().flatten().cloned().collect();
+            return if all.contains(&None) {
+                BTreeSet::from([None])
+            } else {
+                all
+            };
+        }
+        let mut common: Option<BTreeSet<Option<String>>> = None;
+        for set in self.ips.values() {
+            // An unrestricted input accepts whatever the others allow.
+            if set.contains(&None) {
+                continue;
+            }
+            common = Some(match common {
+                Some(common) => common.intersection(set).cloned().collect(),
+                None => set.clone(),
+            });
+        }
+        common.unwrap_or_else(|| BTreeSet::from([None]))
+    }
+}
+
+fn factor_key(endpoint: &WhitelistEndpoint) -> EndpointFingerprint {
+    let mut key = endpoint.fingerprint();
+    if endpoint.domain.is_some() {
+        key.1 = None;
+    }
+    key
+}
+
+/// Merges whitelists produced by parallel jobs using the engine's
+/// deduplication (fingerprint by domain/IP/port/protocol/ASN/process) and
+/// factorization (one IP set per domain endpoint).
+///
+/// `inputs` pairs each whitelist with a label (typically the job name) that is
+/// appended to the endpoint description when `tag_sources` is set.
+pub fn merge(
+    inputs: &[(String, WhitelistsJSON)],
+    mode: MergeMode,
+    tag_sources: bool,
+) -> WhitelistsJSON {
+    let mut names: Vec<&str> = Vec::new();
+    for (_, input) in inputs {
+        for info in &input.whitelists {
+            if !names.contains(&info.name.as_str()) {
+                names.push(&info.name);
+            }
+        }
+    }
+
+    let mut whitelists = Vec::new();
+    for name in names {
+        let present: Vec<(usize, &WhitelistInfo)> = inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, input))| input.get(name).map(|info| (i, info)))
+            .collect();
+        if mode == MergeMode::Intersect && present.len() != inputs.len() {
+            continue;
+        }
+
+        let mut order: Vec<EndpointFingerprint> = Vec::new();
+        let mut factored: HashMap<EndpointFingerprint, Factored> = HashMap::new();
+        let mut extends: Vec<String> = Vec::new();
+        for (source, info) in &present {
+            for parent in info.extends.iter().flatten() {
+                if !extends.contains(parent) {
+                    extends.push(parent.clone());
+                }
+            }
+            for endpoint in &info.endpoints {
+                let key = factor_key(endpoint);
+                let entry = factored.entry(key.clone()).or_insert_with(|| {
+                    order.push(key);
+                    Factored {
+                        template: endpoint.clone(),
+                        ips: BTreeMap::new(),
+                    }
+                });
+                entry
+                    .ips
+                    .entry(*source)
+                    .or_default()
+                    .insert(endpoint.ip.clone());
+            }
+        }
+
+        let mut endpoints = Vec::new();
+        for key in order {
+            let entry = &factored[&key];
+            if mode == MergeMode::Intersect && entry.ips.len() != inputs.len() {
+                continue;
+            }
+            let ips = entry.merged_ips(mode);
+            if ips.is_empty() {
+                continue;
+            }
+            let mut template = entry.template.clone();
+            if tag_sources {
+                let labels: Vec<&str> = entry.ips.keys().map(|i| inputs[*i].0.as_str()).collect();
+                let tag = format!("[jobs: {}]", labels.join(", "));
+                template.description = Some(match template.description.take() {
+                    Some(description) => format!("{} {}", description, tag),
+                    None => tag,
+                });
+            }
+            if ips.contains(&None) {
+                template.ip = None;
+                endpoints.push(template);
+            } else {
+                for ip in ips.iter().flatten() {
+                    let mut endpoint = template.clone();
+                    endpoint.ip = Some(ip.clone());
+                    endpoints.push(endpoint);
+                }
+            }
+        }
+
+        whitelists.push(WhitelistInfo {
+            name: name.to_string(),
+            extends: if extends.is_empty() {
+                None
+            } else {
+                Some(extends)
+            },
+            endpoints,
+        });
+    }
+
+    WhitelistsJSON {
+        date: inputs
+            .first()
+            .map(|(_, input)| input.date.clone())
+            .unwrap_or_default(),
+        // Merged content is no longer covered by any input signature.
+        signature: None,
+        whitelists,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(label: &str, endpoints: &str) -> (String, WhitelistsJSON) {
+        let json = format!(
+            r#"{{"date":"d","whitelists":[{{"name":"custom_whitelist","endpoints":{}}}]}}"#,
+            endpoints
+        );
+        (label.to_string(), WhitelistsJSON::from_json(&json).unwrap())
+    }
+
+    fn inputs() -> Vec<(String, WhitelistsJSON)> {
+        vec![
+            input(
+                "linux",
+                r#"[{"domain":"github.com","ip":"140.82.114.3","port":443,"protocol":"TCP"},
+                    {"domain":"registry.npmjs.org","port":443,"protocol":"TCP"},
+                    {"ip":"1.2.3.4","port":53,"protocol":"UDP"}]"#,
+            ),
+            input(
+                "macos",
+                r#"[{"domain":"github.com","ip":"140.82.114.4","port":443,"protocol":"TCP"},
+                    {"domain":"registry.npmjs.org","ip":"104.16.0.1","port":443,"protocol":"TCP"}]"#,
+            ),
+        ]
+    }
+
+    #[test]
+    fn union_merges_ip_sets() {
+        let merged = merge(&inputs(), MergeMode::Union, false);
+        let endpoints = &merged.whitelists[0].endpoints;
+        let labels: Vec<String> = endpoints
+            .iter()
+            .map(|e| format!("{:?}/{:?}", e.domain, e.ip))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                r#"Some("github.com")/Some("140.82.114.3")"#,
+                r#"Some("github.com")/Some("140.82.114.4")"#,
+                r#"Some("registry.npmjs.org")/None"#,
+                r#"None/Some("1.2.3.4")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn intersect_keeps_common_entries_and_tags() {
+        let merged = merge(&inputs(), MergeMode::Intersect, true);
+        let endpoints = &merged.whitelists[0].endpoints;
+        // github.com has no IP in common; the unrestricted npm entry defers
+        // to the IP the other job saw.
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0].domain.as_deref(), Some("registry.npmjs.org"));
+        assert_eq!(endpoints[0].ip.as_deref(), Some("104.16.0.1"));
+        assert_eq!(
+            endpoints[0].description.as_deref(),
+            Some("[jobs: linux, macos]")
+        );
+    }
+
+    #[test]
+    fn intersect_intersects_ip_sets() {
+        let inputs = vec![
+            input(
+                "a",
+                r#"[{"domain":"github.com","ip":"140.82.114.3","port":443,"protocol":"TCP"},
+                    {"domain":"github.com","ip":"140.82.114.4","port":443,"protocol":"TCP"}]"#,
+            ),
+            input(
+                "b",
+                r#"[{"domain":"github.com","ip":"140.82.114.4","port":443,"protocol":"TCP"},
+                    {"domain":"github.com","ip":"140.82.114.5","port":443,"protocol":"TCP"}]"#,
+            ),
+        ];
+        let merged = merge(&inputs, MergeMode::Intersect, false);
+        let ips: Vec<Option<&str>> = merged.whitelists[0]
+            .endpoints
+            .iter()
+            .map(|e| e.ip.as_deref())
+            .collect();
+        assert_eq!(ips, vec![Some("140.82.114.4")]);
+
+        let merged = merge(&inputs, MergeMode::Union, false);
+        assert_eq!(merged.whitelists[0].endpoints.len(), 3);
+    }
+}
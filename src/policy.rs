@@ -0,0 +1,508 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use flodbadd::sessions::SessionInfo;
+
+use crate::categories::CategoryOptions;
+use crate::dns::DomainOptions;
+use crate::explain::looks_like_reverse_dns;
+use crate::geo::{AsnDatabase, AsnInfo};
+use crate::ingress::{is_inbound, IngressOptions};
+use crate::process::{ProcessPolicy, ProcessVerdict};
+use crate::volume::VolumeLimits;
+use crate::whitelist::ip_matches;
+
+/// Placeholders Flodbadd uses for a destination that has no name yet.
+pub const UNRESOLVED_DOMAINS: &[&str] = &["Unknown", RESOLVING_DOMAIN];
+
+/// Placeholder for a destination whose DNS attribution is still pending.
+pub const RESOLVING_DOMAIN: &str = "Resolving";
+
+/// How long `scan` waits at the end for destinations still resolving.
+pub const RESOLUTION_GRACE: Duration = Duration::from_secs(5);
+
+/// Cloud instance metadata services: AWS/Azure/GCP/OCI (IPv4 and AWS
+/// IPv6), the ECS task metadata endpoint and Alibaba Cloud.
+pub const METADATA_ADDRESSES: &[&str] = &[
+    "169.254.169.254",
+    "fd00:ec2::254",
+    "169.254.170.2",
+    "100.100.100.200",
+];
+
+/// What to do when a policy matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Action {
+    #[default]
+    Off,
+    /// Report the session without failing the run.
+    Warn,
+    /// Report the session as a violation.
+    Fail,
+}
+
+impl Action {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "off" => Ok(Action::Off),
+            "warn" => Ok(Action::Warn),
+            "fail" => Ok(Action::Fail),
+            _ => bail!("Invalid policy action '{}' (use off, warn or fail)", value),
+        }
+    }
+}
+
+/// Egress policies checked by `scan` and `watch` independently of the
+/// whitelist.
+#[derive(Debug, Default, Clone)]
+pub struct PolicyOptions {
+    /// Flag egress to addresses without Forward DNS or SNI attribution
+    /// (heuristic, see `direct_ip_reason`).
+    pub forbid_direct_ip: bool,
+    /// Networks exempt from the direct-IP rule (internal infrastructure).
+    pub direct_ip_allow: Vec<String>,
+    /// Action on access to a cloud metadata service.
+    pub imds: Action,
+    /// Process names or paths allowed to reach the metadata service.
+    pub imds_allow_process: Vec<String>,
+    /// ISO 3166 country codes egress may never reach.
+    pub deny_countries: Vec<String>,
+    /// When set, the only countries egress may reach.
+    pub allow_countries: Vec<String>,
+    /// AS numbers egress may never reach.
+    pub deny_asns: Vec<u32>,
+    /// When set, AS numbers egress may reach besides `allow_countries`.
+    pub allow_asns: Vec<u32>,
+    /// Offline ASN/country database, preferred over Flodbadd's own lookup.
+    pub asn_database: AsnDatabase,
+    /// Outbound volume and connection thresholds.
+    pub volume: VolumeLimits,
+    /// DNS tunneling and DGA heuristics.
+    pub domains: DomainOptions,
+    /// New listening sockets and inbound sessions from outside.
+    pub ingress: IngressOptions,
+    /// Paste sites, tunnels, Tor and mining pools.
+    pub categories: CategoryOptions,
+    /// Executables allowed or denied to open egress.
+    pub processes: ProcessPolicy,
+}
+
+/// A session breaking one of the policies.
+pub struct PolicyViolation {
+    pub rule: &'static str,
+    pub session: SessionInfo,
+    pub detail: String,
+    /// Whether the violation fails the run, or is only a warning.
+    pub enforced: bool,
+}
+
+/// Checks that `value` is an IP address or CIDR range.
+pub fn validate_network(value: &str) -> Result<()> {
+    let (address, prefix) = match value.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (value, None),
+    };
+    let Ok(address) = address.parse::<IpAddr>() else {
+        bail!("Invalid IP or CIDR '{}'", value);
+    };
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    if let Some(prefix) = prefix {
+        match prefix.parse::<u8>() {
+            Ok(bits) if bits <= max => {}
+            _ => bail!("Invalid prefix length in '{}'", value),
+        }
+    }
+    Ok(())
+}
+
+/// Whether a session leaves the host towards a non-local destination.
+pub fn is_egress(session: &SessionInfo) -> bool {
+    (session.is_self_src || session.is_local_src) && !session.is_local_dst
+}
+
+/// Why a destination counts as direct-IP egress, or `None` when it has a
+/// name, is still resolving, is DNS itself, or is allowlisted. Flodbadd does
+/// not record whether a name came from a DNS answer, the SNI or a PTR
+/// lookup, so reverse DNS names are only recognised when they embed the
+/// address; a PTR name such as `mail.example.org` passes as attribution.
+pub fn direct_ip_reason(
+    domain: Option<&str>,
+    ip: &IpAddr,
+    port: u16,
+    allow: &[String],
+) -> Option<String> {
+    // Resolver traffic cannot itself be attributed by DNS.
+    if port == 53 || allow.iter().any(|network| ip_matches(network, ip)) {
+        return None;
+    }
+    match domain {
+        Some(RESOLVING_DOMAIN) => None,
+        Some(d) if UNRESOLVED_DOMAINS.contains(&d) || d.trim().is_empty() => {
+            Some("no Forward DNS or SNI attribution".to_string())
+        }
+        Some(d) if d.parse::<IpAddr>().is_ok() => {
+            Some("no Forward DNS or SNI attribution".to_string())
+        }
+        Some(d) if looks_like_reverse_dns(d, ip) => Some(format!("only reverse DNS name {}", d)),
+        Some(_) => None,
+        None => Some("no Forward DNS or SNI attribution".to_string()),
+    }
+}
+
+/// Why a session to `ip` counts as metadata-service access, or `None` when
+/// `ip` is not a metadata address or the process is allowed.
+pub fn imds_reason(ip: &IpAddr, process: Option<(&str, &str)>, allow: &[String]) -> Option<String> {
+    if !METADATA_ADDRESSES
+        .iter()
+        .any(|address| address.parse::<IpAddr>().ok() == Some(*ip))
+    {
+        return None;
+    }
+    match process {
+        Some((name, path)) if allow.iter().any(|a| a == name || a == path) => None,
+        Some((name, _)) => Some(format!("metadata service accessed by {}", name)),
+        None => Some("metadata service accessed by an unknown process".to_string()),
+    }
+}
+
+/// Normalizes an ISO 3166 alpha-2 country code.
+pub fn parse_country(value: &str) -> Result<String> {
+    let code = value.trim();
+    if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        bail!(
+            "Invalid country code '{}' (use ISO 3166 alpha-2, e.g. DE)",
+            value
+        );
+    }
+    Ok(code.to_ascii_uppercase())
+}
+
+/// Parses an AS number written as `13335` or `AS13335`.
+pub fn parse_asn(value: &str) -> Result<u32> {
+    let value = value.trim();
+    let digits = value
+        .strip_prefix("AS")
+        .or_else(|| value.strip_prefix("as"))
+        .unwrap_or(value);
+    match digits.parse::<u32>() {
+        Ok(asn) if asn > 0 => Ok(asn),
+        _ => bail!("Invalid AS number '{}'", value),
+    }
+}
+
+impl PolicyOptions {
+    fn has_geo_rules(&self) -> bool {
+        !(self.deny_countries.is_empty()
+            && self.allow_countries.is_empty()
+            && self.deny_asns.is_empty()
+            && self.allow_asns.is_empty())
+    }
+
+    /// Why a destination breaks the country/ASN rules, or `None` when it
+    /// does not. Denials win over allowances; with an allow list set, a
+    /// destination of unknown origin is a violation too.
+    pub fn geo_reason(&self, info: Option<&AsnInfo>) -> Option<String> {
+        let Some(info) = info else {
+            return (!self.allow_countries.is_empty() || !self.allow_asns.is_empty())
+                .then(|| "no ASN or country information".to_string());
+        };
+        let origin = format!("AS{} {} ({})", info.as_number, info.owner, info.country);
+        if self.deny_asns.contains(&info.as_number) {
+            return Some(format!("denied AS: {}", origin));
+        }
+        if self.deny_countries.contains(&info.country) {
+            return Some(format!("denied country: {}", origin));
+        }
+        if self.allow_countries.is_empty() && self.allow_asns.is_empty() {
+            return None;
+        }
+        if self.allow_countries.contains(&info.country) || self.allow_asns.contains(&info.as_number)
+        {
+            return None;
+        }
+        Some(format!("not an allowed country or AS: {}", origin))
+    }
+
+    /// Origin of a session's destination, from the offline database when it
+    /// knows the address, else from Flodbadd.
+    fn destination_origin(&self, session: &SessionInfo) -> Option<AsnInfo> {
+        if let Some(info) = self.asn_database.lookup(&session.session.dst_ip) {
+            return Some(info.clone());
+        }
+        session.dst_asn.as_ref().map(|asn| AsnInfo {
+            as_number: asn.as_number,
+            country: asn.country.to_ascii_uppercase(),
+            owner: asn.owner.clone(),
+        })
+    }
+
+    /// Whether the direct-IP rule has to wait for `session`, whose
+    /// destination is still being resolved.
+    pub fn awaits_resolution(&self, session: &SessionInfo) -> bool {
+        self.forbid_direct_ip
+            && is_egress(session)
+            && session.dst_domain.as_deref() == Some(RESOLVING_DOMAIN)
+    }
+
+    /// The direct-IP violation of an egress `session`, if any. Sessions still
+    /// resolving pass; `watch` checks them again once they have a name.
+    pub fn direct_ip_violation(&self, session: &SessionInfo) -> Option<PolicyViolation> {
+        if !self.forbid_direct_ip {
+            return None;
+        }
+        let detail = direct_ip_reason(
+            session.dst_domain.as_deref(),
+            &session.session.dst_ip,
+            session.session.dst_port,
+            &self.direct_ip_allow,
+        )?;
+        Some(PolicyViolation {
+            rule: "direct-ip",
+            session: session.clone(),
+            detail,
+            enforced: true,
+        })
+    }
+
+    /// The direct-IP violation of a session whose destination was still
+    /// resolving when the run ended, after the grace period.
+    pub fn unresolved_violation(&self, session: &SessionInfo) -> Option<PolicyViolation> {
+        if !self.awaits_resolution(session) {
+            return None;
+        }
+        direct_ip_reason(
+            None,
+            &session.session.dst_ip,
+            session.session.dst_port,
+            &self.direct_ip_allow,
+        )?;
+        Some(PolicyViolation {
+            rule: "direct-ip",
+            session: session.clone(),
+            detail: "destination still resolving when the run ended".to_string(),
+            enforced: true,
+        })
+    }
+
+    pub fn check(&self, sessions: &[SessionInfo]) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        for session in sessions {
+            // Metadata services are link-local, so this check is not limited
+            // to egress.
+            if self.imds != Action::Off {
+                let process = session
+                    .l7
+                    .as_ref()
+                    .map(|l7| (l7.process_name.as_str(), l7.process_path.as_str()));
+                if let Some(detail) =
+                    imds_reason(&session.session.dst_ip, process, &self.imds_allow_process)
+                {
+                    violations.push(PolicyViolation {
+                        rule: "imds",
+                        session: session.clone(),
+                        detail,
+                        enforced: self.imds == Action::Fail,
+                    });
+                }
+            }
+            if self.ingress.action != Action::Off && is_inbound(session) {
+                let process = session
+                    .l7
+                    .as_ref()
+                    .map(|l7| (l7.process_name.as_str(), l7.process_path.as_str()));
+                if !self.ingress.is_allowed(
+                    session.session.dst_port,
+                    process,
+                    Some(&session.session.src_ip),
+                ) {
+                    violations.push(PolicyViolation {
+                        rule: "inbound",
+                        session: session.clone(),
+                        detail: format!(
+                            "inbound connection from {} to local port {}",
+                            session.session.src_ip, session.session.dst_port
+                        ),
+                        enforced: self.ingress.action == Action::Fail,
+                    });
+                }
+            }
+            if !is_egress(session) {
+                continue;
+            }
+            violations.extend(self.direct_ip_violation(session));
+            let process = session
+                .l7
+                .as_ref()
+                .map(|l7| (l7.process_name.as_str(), l7.process_path.as_str()));
+            if !self.processes.is_empty() {
+                let detail = match self.processes.verdict(process) {
+                    ProcessVerdict::Allowed => None,
+                    ProcessVerdict::Denied(detail) => Some((detail, true)),
+                    // Attribution can be missing for short-lived processes,
+                    // so this is only a warning.
+                    ProcessVerdict::Unattributed => {
+                        Some(("no process attribution".to_string(), false))
+                    }
+                };
+                if let Some((detail, enforced)) = detail {
+                    violations.push(PolicyViolation {
+                        rule: "process",
+                        session: session.clone(),
+                        detail,
+                        enforced,
+                    });
+                }
+            }
+            for (found, action) in self.categories.check(
+                session.dst_domain.as_deref(),
+                &session.session.dst_ip,
+                session.session.dst_port,
+                process,
+            ) {
+                violations.push(PolicyViolation {
+                    rule: "category",
+                    session: session.clone(),
+                    detail: format!("{} service ({})", found.category, found.entry),
+                    enforced: action == Action::Fail,
+                });
+            }
+            if self.has_geo_rules() {
+                let origin = self.destination_origin(session);
+                if let Some(detail) = self.geo_reason(origin.as_ref()) {
+                    violations.push(PolicyViolation {
+                        rule: "geo",
+                        session: session.clone(),
+                        detail,
+                        enforced: true,
+                    });
+                }
+            }
+        }
+        violations
+    }
+}
+
+/// Prints warnings and violations, one line each with the rule that flagged
+/// it, and returns the sessions of enforced violations.
+pub fn report(violations: Vec<PolicyViolation>) -> Vec<SessionInfo> {
+    let (enforced, warnings): (Vec<_>, Vec<_>) = violations.into_iter().partition(|v| v.enforced);
+    if !warnings.is_empty() {
+        println!("Policy warnings: {}", warnings.len());
+        print_violations(&warnings);
+    }
+    if !enforced.is_empty() {
+        println!("Policy violations detected: {}", enforced.len());
+        print_violations(&enforced);
+    }
+    enforced.into_iter().map(|v| v.session).collect()
+}
+
+fn print_violations(violations: &[PolicyViolation]) {
+    for violation in violations {
+        let s = &violation.session;
+        println!(
+            "  - [{}] {} -> {}:{} ({}): {}",
+            violation.rule,
+            s.session.src_ip,
+            s.dst_domain
+                .clone()
+                .unwrap_or_else(|| s.session.dst_ip.to_string()),
+            s.session.dst_port,
+            s.l7.as_ref()
+                .map(|l7| l7.process_name.as_str())
+                .unwrap_or("unknown process"),
+            violation.detail
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_ip_egress() {
+        let ip: IpAddr = "185.199.111.133".parse().unwrap();
+        let allow = vec!["10.0.0.0/8".to_string(), "185.199.108.0/22".to_string()];
+        assert!(direct_ip_reason(Some("github.com"), &ip, 443, &[]).is_none());
+        assert!(direct_ip_reason(None, &ip, 443, &[]).is_some());
+        assert!(direct_ip_reason(Some("Unknown"), &ip, 443, &[]).is_some());
+        assert!(direct_ip_reason(Some(" "), &ip, 443, &[]).is_some());
+        // Attribution is pending, not missing
+        assert!(direct_ip_reason(Some("Resolving"), &ip, 443, &[]).is_none());
+        assert_eq!(
+            direct_ip_reason(Some("cdn-185-199-111-133.github.com"), &ip, 443, &[]).as_deref(),
+            Some("only reverse DNS name cdn-185-199-111-133.github.com")
+        );
+        assert!(direct_ip_reason(None, &ip, 443, &allow).is_none());
+        assert!(direct_ip_reason(None, &ip, 53, &[]).is_none());
+    }
+
+    #[test]
+    fn metadata_service_access() {
+        let imds: IpAddr = "169.254.169.254".parse().unwrap();
+        let allow = vec!["/usr/bin/amazon-ssm-agent".to_string()];
+        assert_eq!(
+            imds_reason(&imds, Some(("curl", "/usr/bin/curl")), &allow).as_deref(),
+            Some("metadata service accessed by curl")
+        );
+        assert!(imds_reason(
+            &imds,
+            Some(("amazon-ssm-agent", "/usr/bin/amazon-ssm-agent")),
+            &allow
+        )
+        .is_none());
+        assert!(imds_reason(&"fd00:ec2::254".parse().unwrap(), None, &allow).is_some());
+        assert!(imds_reason(&"169.254.169.253".parse().unwrap(), None, &[]).is_none());
+        assert_eq!(Action::parse("warn").unwrap(), Action::Warn);
+        assert!(Action::parse("block").is_err());
+    }
+
+    #[test]
+    fn country_and_asn_rules() {
+        let info = |as_number, country: &str| AsnInfo {
+            as_number,
+            country: country.to_string(),
+            owner: "EXAMPLE".to_string(),
+        };
+        let deny = PolicyOptions {
+            deny_countries: vec!["RU".to_string()],
+            deny_asns: vec![64500],
+            ..Default::default()
+        };
+        assert_eq!(
+            deny.geo_reason(Some(&info(12345, "RU"))).as_deref(),
+            Some("denied country: AS12345 EXAMPLE (RU)")
+        );
+        assert!(deny.geo_reason(Some(&info(64500, "US"))).is_some());
+        assert!(deny.geo_reason(Some(&info(13335, "US"))).is_none());
+        assert!(deny.geo_reason(None).is_none());
+
+        let allow = PolicyOptions {
+            allow_countries: vec!["DE".to_string(), "FR".to_string()],
+            allow_asns: vec![13335],
+            deny_asns: vec![64501],
+            ..Default::default()
+        };
+        assert!(allow.geo_reason(Some(&info(3320, "DE"))).is_none());
+        assert!(allow.geo_reason(Some(&info(13335, "US"))).is_none());
+        assert!(allow.geo_reason(Some(&info(16509, "US"))).is_some());
+        assert!(allow.geo_reason(Some(&info(64501, "DE"))).is_some());
+        assert!(allow.geo_reason(None).is_some());
+
+        assert_eq!(parse_country("de").unwrap(), "DE");
+        assert!(parse_country("DEU").is_err());
+        assert_eq!(parse_asn("AS13335").unwrap(), 13335);
+        assert!(parse_asn("ASX").is_err());
+    }
+
+    #[test]
+    fn networks_are_validated() {
+        assert!(validate_network("10.0.0.0/8").is_ok());
+        assert!(validate_network("fd00::/8").is_ok());
+        assert!(validate_network("192.0.2.1").is_ok());
+        assert!(validate_network("10.0.0.0/33").is_err());
+        assert!(validate_network("internal.corp").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{bail, Context, Result};
+
+use crate::whitelist::{WhitelistInfo, WhitelistsJSON};
+
+/// A whitelist for a package ecosystem or CI service, compiled into the
+/// binary and layered under the custom whitelist with `--preset`.
+pub struct Preset {
+    pub name: &'static str,
+    /// Bumped whenever the endpoint list changes; also the preset file's
+    /// `date`.
+    pub version: &'static str,
+    pub description: &'static str,
+    source: &'static str,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "crates-io",
+        version: "2025.07",
+        description: "crates.io registry and rustup toolchains",
+        source: include_str!("../presets/crates-io.json"),
+    },
+    Preset {
+        name: "docker-hub",
+        version: "2025.07",
+        description: "Docker Hub image pulls",
+        source: include_str!("../presets/docker-hub.json"),
+    },
+    Preset {
+        name: "github-actions",
+        version: "2025.07",
+        description: "GitHub, GitHub Actions runner services and GHCR",
+        source: include_str!("../presets/github-actions.json"),
+    },
+    Preset {
+        name: "go-proxy",
+        version: "2025.07",
+        description: "Go module proxy, checksum database and toolchains",
+        source: include_str!("../presets/go-proxy.json"),
+    },
+    Preset {
+        name: "maven",
+        version: "2025.07",
+        description: "Maven Central and the Gradle plugin portal",
+        source: include_str!("../presets/maven.json"),
+    },
+    Preset {
+        name: "npm",
+        version: "2025.07",
+        description: "npm and Yarn registries, Node.js downloads",
+        source: include_str!("../presets/npm.json"),
+    },
+    Preset {
+        name: "pypi",
+        version: "2025.07",
+        description: "PyPI index and package files",
+        source: include_str!("../presets/pypi.json"),
+    },
+];
+
+pub fn names() -> Vec<&'static str> {
+    PRESETS.iter().map(|p| p.name).collect()
+}
+
+pub fn get(name: &str) -> Result<&'static Preset> {
+    match PRESETS.iter().find(|p| p.name == name) {
+        Some(preset) => Ok(preset),
+        None => bail!(
+            "Unknown preset '{}' (available: {})",
+            name,
+            names().join(", ")
+        ),
+    }
+}
+
+impl Preset {
+    /// Name of the whitelist the preset contributes, which the custom
+    /// whitelist extends.
+    pub fn whitelist_name(&self) -> String {
+        format!("preset_{}", self.name.replace('-', "_"))
+    }
+
+    pub fn whitelists(&self) -> Result<WhitelistsJSON> {
+        WhitelistsJSON::from_json(self.source)
+            .with_context(|| format!("Invalid built-in preset '{}'", self.name))
+    }
+
+    /// The preset's whitelist, with every endpoint justified by the preset
+    /// itself so that `--strict-whitelist` accepts it.
+    pub fn whitelist(&self) -> Result<WhitelistInfo> {
+        let name = self.whitelist_name();
+        let mut info = self
+            .whitelists()?
+            .whitelists
+            .into_iter()
+            .find(|w| w.name == name)
+            .with_context(|| format!("Preset '{}' has no '{}' whitelist", self.name, name))?;
+        for endpoint in &mut info.endpoints {
+            endpoint
+                .justification
+                .get_or_insert_with(|| format!("Preset {} {}", self.name, self.version));
+        }
+        Ok(info)
+    }
+}
+
+/// Adds the whitelists of `presets` to `whitelists` and makes the custom
+/// whitelist extend them, so custom entries layer on top.
+pub fn layer(whitelists: &mut WhitelistsJSON, presets: &[String]) -> Result<()> {
+    for name in presets {
+        let preset = get(name)?;
+        let info = preset.whitelist()?;
+        let custom = whitelists.custom_whitelist_mut();
+        let extends = custom.extends.get_or_insert_with(Vec::new);
+        if !extends.contains(&info.name) {
+            extends.push(info.name.clone());
+        }
+        whitelists.whitelists.retain(|w| w.name != info.name);
+        whitelists.whitelists.push(info);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        for preset in PRESETS {
+            let whitelists = preset.whitelists().unwrap();
+            assert_eq!(whitelists.date, preset.version, "{}", preset.name);
+            let info = preset.whitelist().unwrap();
+            assert!(!info.endpoints.is_empty(), "{}", preset.name);
+            assert!(info
+                .endpoints
+                .iter()
+                .all(|e| e.domain.is_some() && e.justification.is_some()));
+        }
+        assert!(get("cobol").is_err());
+    }
+
+    #[test]
+    fn presets_layer_under_custom_whitelist() {
+        let mut whitelists = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","extends":["base"],"endpoints":[
+                {"domain":"internal.example.com","port":443,"protocol":"TCP"}]}]}"#,
+        )
+        .unwrap();
+        let presets = vec!["npm".to_string(), "pypi".to_string(), "npm".to_string()];
+        layer(&mut whitelists, &presets).unwrap();
+        let custom = whitelists.get("custom_whitelist").unwrap();
+        assert_eq!(
+            custom.extends.as_deref(),
+            Some(
+                &[
+                    "base".to_string(),
+                    "preset_npm".into(),
+                    "preset_pypi".into()
+                ][..]
+            )
+        );
+        assert_eq!(whitelists.whitelists.len(), 3);
+        let domains: Vec<String> = whitelists
+            .resolve_endpoints("custom_whitelist")
+            .into_iter()
+            .filter_map(|(_, e)| e.domain.clone())
+            .collect();
+        assert!(domains.contains(&"internal.example.com".to_string()));
+        assert!(domains.contains(&"files.pythonhosted.org".to_string()));
+    }
+}
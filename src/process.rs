@@ -0,0 +1,180 @@
+/// Matches `text` against a glob where `*` matches any run of characters
+/// (including `/`) and `?` any single character.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it was tried at.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((after_star, tried)) => {
+                    p = after_star;
+                    t = tried + 1;
+                    backtrack = Some((after_star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Whether a process pattern matches an executable. Patterns containing
+/// `/` match the full path; others match the process name or the file name
+/// of the path.
+pub fn process_matches(pattern: &str, name: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        return glob_matches(pattern, path);
+    }
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    glob_matches(pattern, name) || (!file_name.is_empty() && glob_matches(pattern, file_name))
+}
+
+/// Like [`process_matches`], but ignores the process name when the path is
+/// known: a process can rename itself (`exec -a`, `prctl`), so granting
+/// egress on the name alone would let anything pose as `git`.
+pub fn process_allowed_by(pattern: &str, name: &str, path: &str) -> bool {
+    if path.is_empty() {
+        return process_matches(pattern, name, path);
+    }
+    process_matches(pattern, "", path)
+}
+
+/// Which executables may open egress connections.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessPolicy {
+    /// When set, the only processes allowed to open egress.
+    pub allow: Vec<String>,
+    /// Processes never allowed to open egress, even when allowed above.
+    pub deny: Vec<String>,
+}
+
+/// Outcome of checking one session's process against the policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessVerdict {
+    Allowed,
+    Denied(String),
+    /// The session has no process attribution while an allowlist is set.
+    Unattributed,
+}
+
+impl ProcessPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn verdict(&self, process: Option<(&str, &str)>) -> ProcessVerdict {
+        let Some((name, path)) = process else {
+            return if self.allow.is_empty() {
+                ProcessVerdict::Allowed
+            } else {
+                ProcessVerdict::Unattributed
+            };
+        };
+        if let Some(pattern) = self.deny.iter().find(|p| process_matches(p, name, path)) {
+            return ProcessVerdict::Denied(format!(
+                "{} ({}) may not open egress (denied by '{}')",
+                name, path, pattern
+            ));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|p| process_allowed_by(p, name, path)) {
+            return ProcessVerdict::Denied(format!(
+                "{} ({}) is not in the egress process allowlist",
+                name, path
+            ));
+        }
+        ProcessVerdict::Allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("python*", "python3.11"));
+        assert!(glob_matches("/usr/bin/*", "/usr/bin/git"));
+        assert!(glob_matches(
+            "/home/*/node_modules/*/bin/*",
+            "/home/runner/node_modules/a/b/bin/x"
+        ));
+        assert!(glob_matches("?sh", "zsh"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("sh", "bash"));
+        assert!(!glob_matches("python?", "python3.11"));
+        assert!(glob_matches("*a*b", "xaxxbxb"));
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let policy = ProcessPolicy {
+            allow: [
+                "git",
+                "npm",
+                "node",
+                "cargo",
+                "docker*",
+                "/opt/hostedtoolcache/*",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            deny: ["bash", "sh", "python*", "node"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        };
+        assert_eq!(
+            policy.verdict(Some(("git", "/usr/bin/git"))),
+            ProcessVerdict::Allowed
+        );
+        assert_eq!(
+            policy.verdict(Some(("dockerd", "/usr/bin/dockerd"))),
+            ProcessVerdict::Allowed
+        );
+        assert_eq!(
+            policy.verdict(Some(("go", "/opt/hostedtoolcache/go/1.22/x64/bin/go"))),
+            ProcessVerdict::Allowed
+        );
+        assert!(matches!(
+            policy.verdict(Some(("node", "/usr/bin/node"))),
+            ProcessVerdict::Denied(d) if d.contains("denied by 'node'")
+        ));
+        assert!(matches!(
+            policy.verdict(Some(("python3", "/usr/bin/python3.11"))),
+            ProcessVerdict::Denied(_)
+        ));
+        assert!(matches!(
+            policy.verdict(Some(("curl", "/usr/bin/curl"))),
+            ProcessVerdict::Denied(d) if d.contains("allowlist")
+        ));
+        assert_eq!(policy.verdict(None), ProcessVerdict::Unattributed);
+        // A renamed process is judged by its executable, not its name.
+        assert!(matches!(
+            policy.verdict(Some(("git", "/tmp/payload"))),
+            ProcessVerdict::Denied(d) if d.contains("allowlist")
+        ));
+        assert_eq!(policy.verdict(Some(("git", ""))), ProcessVerdict::Allowed);
+
+        let deny_only = ProcessPolicy {
+            deny: vec!["sh".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(deny_only.verdict(None), ProcessVerdict::Allowed);
+        assert_eq!(
+            deny_only.verdict(Some(("bash", "/bin/bash"))),
+            ProcessVerdict::Allowed
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use flodbadd::sessions::SessionInfo;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Loads the sessions of a saved `scan --output report`.
+///
+/// The file may be the bare JSON array or the full captured stdout of `scan`,
+/// where the array follows the human-readable session report.
+pub fn load_sessions(path: &str) -> Result<Vec<SessionInfo>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read report '{}'", path))?;
+    extract_json_array(&text).with_context(|| format!("No session array found in '{}'", path))
+}
+
+/// Parses `text` as a JSON array, or failing that, the last array that starts
+/// on its own line (`[` or `[]`) and runs to the end of the output.
+pub fn extract_json_array<T: DeserializeOwned>(text: &str) -> Result<Vec<T>> {
+    if let Ok(items) = serde_json::from_str::<Vec<T>>(text) {
+        return Ok(items);
+    }
+
+    let mut offset = 0;
+    let mut starts = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed == "[" || trimmed == "[]" {
+            starts.push(offset + line.find('[').unwrap_or(0));
+        }
+        offset += line.len();
+    }
+
+    for start in starts.into_iter().rev() {
+        let mut stream = serde_json::Deserializer::from_str(&text[start..]).into_iter::<Vec<T>>();
+        if let Some(Ok(items)) = stream.next() {
+            return Ok(items);
+        }
+    }
+    bail!("not a JSON session report")
+}
+
+/// Fields added to the sessions of `scan --output report` (lineage,
+/// blacklist hits, anomaly explanations), keyed by session uid. The report
+/// stays an array of sessions, so it still loads with [`load_sessions`].
+#[derive(Default)]
+pub struct Annotations {
+    fields: HashMap<String, Map<String, Value>>,
+}
+
+impl Annotations {
+    pub fn add<T: Serialize>(&mut self, session: &SessionInfo, key: &str, value: &T) -> Result<()> {
+        self.fields
+            .entry(session.uid.clone())
+            .or_default()
+            .insert(key.to_string(), serde_json::to_value(value)?);
+        Ok(())
+    }
+
+    /// `sessions` as JSON objects carrying their annotations.
+    pub fn apply(&self, sessions: &[SessionInfo]) -> Result<Vec<Value>> {
+        sessions
+            .iter()
+            .map(|session| {
+                let mut value = serde_json::to_value(session)?;
+                if let (Some(fields), Some(object)) =
+                    (self.fields.get(&session.uid), value.as_object_mut())
+                {
+                    object.extend(fields.clone());
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn bare_array() {
+        let items: Vec<Value> = extract_json_array(r#"[{"uid":"a"},{"uid":"b"}]"#).unwrap();
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn array_after_session_log() {
+        let raw = "Whitelist exceptions detected: 1\n\n=== Session Report ===\n[tcp] 10.0.0.1:1234 -> [1.2.3.4]:443\n[\n  {\n    \"uid\": \"a\"\n  }\n]\n";
+        let items: Vec<Value> = extract_json_array(raw).unwrap();
+        assert_eq!(items[0]["uid"], "a");
+    }
+
+    #[test]
+    fn empty_report() {
+        let items: Vec<Value> = extract_json_array("=== Session Report ===\n[]\n").unwrap();
+        assert!(items.is_empty());
+        assert!(extract_json_array::<Value>("no json here").is_err());
+    }
+}
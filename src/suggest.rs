@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use similar::TextDiff;
+
+use crate::whitelist::{EndpointFingerprint, WhitelistsJSON, WriteOptions};
+use crate::yaml;
+
+/// Removes from `generated` every endpoint already present in `existing`,
+/// leaving the smallest set of entries that would have allowed the
+/// violating sessions.
+pub fn delta(existing: &WhitelistsJSON, generated: &WhitelistsJSON) -> WhitelistsJSON {
+    let known: HashSet<EndpointFingerprint> = existing
+        .whitelists
+        .iter()
+        .flat_map(|w| w.endpoints.iter().map(|e| e.fingerprint()))
+        .collect();
+    let mut delta = generated.clone();
+    for info in &mut delta.whitelists {
+        info.endpoints.retain(|e| !known.contains(&e.fingerprint()));
+    }
+    delta.whitelists.retain(|w| !w.endpoints.is_empty());
+    delta.signature = None;
+    delta.canonicalize();
+    delta
+}
+
+/// Whether the endpoints of whitelist `name` (of any whitelist when `None`)
+/// spell out missing fields as `null` in the parsed original document.
+fn has_null_fields(document: &Value, name: Option<&str>) -> bool {
+    document["whitelists"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|w| name.map(|n| w["name"] == n).unwrap_or(true))
+        .flat_map(|w| w["endpoints"].as_array().into_iter().flatten())
+        .filter_map(|e| e.as_object())
+        .any(|e| e.values().any(Value::is_null))
+}
+
+fn parse_document(path: &str, original: &str) -> Value {
+    let parsed = if yaml::is_yaml_path(path) {
+        serde_norway::from_str(original).ok()
+    } else {
+        serde_json::from_str(original).ok()
+    };
+    parsed.unwrap_or(Value::Null)
+}
+
+/// Renders `whitelists` the way `path` is stored: YAML with its comments
+/// kept, or canonical JSON. Null fields are written only when the original
+/// file spells them out.
+fn render(path: &str, original: &str, whitelists: &WhitelistsJSON) -> Result<String> {
+    let options = WriteOptions {
+        omit_null: !original.is_empty() && !has_null_fields(&parse_document(path, original), None),
+        date: None,
+    };
+    if yaml::is_yaml_path(path) {
+        let comments = yaml::parse(original).map(|(_, c)| c).unwrap_or_default();
+        Ok(yaml::to_yaml(whitelists, &comments, &options))
+    } else {
+        whitelists.to_canonical_json(&options)
+    }
+}
+
+/// Inserts the endpoints of `delta` into the original text of the YAML
+/// whitelist at `path`, after the last entry of the same whitelist, so the
+/// patch only adds lines and keeps every comment. Returns `None` when the
+/// layout is not recognized.
+fn insert_yaml(path: &str, original: &str, delta: &WhitelistsJSON) -> Option<String> {
+    let document = parse_document(path, original);
+    let mut insertions = Vec::new();
+    for info in &delta.whitelists {
+        let nulls = has_null_fields(&document, Some(&info.name));
+        let (at, indent) = yaml::endpoints_end(original, &info.name)?;
+        let mut text = String::new();
+        if !original[..at].ends_with('\n') {
+            text.push('\n');
+        }
+        for endpoint in &info.endpoints {
+            text.push_str(&yaml::endpoint_item(endpoint, indent, !nulls));
+        }
+        insertions.push((at, text));
+    }
+    insertions.sort_by_key(|(at, _)| std::cmp::Reverse(*at));
+    let mut text = original.to_string();
+    for (at, inserted) in insertions {
+        text.insert_str(at, &inserted);
+    }
+    Some(text)
+}
+
+/// Unified diff turning `old` into `new`, with `a/` and `b/` prefixed paths
+/// so that it applies with `git apply` or `patch -p1`.
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let path = path.trim_start_matches("./");
+    let old_header = if old.is_empty() {
+        "/dev/null".to_string()
+    } else {
+        format!("a/{}", path)
+    };
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(&old_header, &format!("b/{}", path))
+        .to_string()
+}
+
+/// The whitelist at `path` with `delta` added. YAML files get the new
+/// entries inserted in place; anything else is written in canonical form
+/// (see [`WhitelistsJSON::to_canonical_json`]), so the patch only adds
+/// lines when the file was canonical already.
+fn patched(
+    path: &str,
+    original: &str,
+    existing: WhitelistsJSON,
+    delta: &WhitelistsJSON,
+) -> Result<String> {
+    let inserted = if yaml::is_yaml_path(path) && !original.is_empty() {
+        insert_yaml(path, original, delta)
+    } else {
+        None
+    };
+    if let Some(text) = inserted {
+        return Ok(text);
+    }
+    if !original.is_empty() && render(path, original, &existing)? != original {
+        println!(
+            "'{}' is not in canonical form; the patch also rewrites it in canonical form",
+            path
+        );
+    }
+    let mut updated = existing;
+    updated.augment(delta);
+    render(path, original, &updated)
+}
+
+/// Writes the suggested delta to `path` as whitelist JSON and, next to it
+/// with a `.patch` extension, as a unified diff against `input` (the custom
+/// whitelist in use, if any). Returns the number of suggested endpoints.
+pub fn emit(path: &str, input: Option<&str>, generated: &WhitelistsJSON) -> Result<usize> {
+    let input = input.filter(|p| Path::new(p).exists());
+    let original = match input {
+        Some(p) => std::fs::read_to_string(p)
+            .with_context(|| format!("Failed to read whitelist '{}'", p))?,
+        None => String::new(),
+    };
+    let existing = match input {
+        Some(p) => WhitelistsJSON::load(p)?,
+        None => WhitelistsJSON::empty(),
+    };
+
+    let delta = delta(&existing, generated);
+    let count = delta.whitelists.iter().map(|w| w.endpoints.len()).sum();
+    std::fs::write(path, delta.to_canonical_json(&WriteOptions::default())?)
+        .with_context(|| format!("Failed to write suggestion '{}'", path))?;
+
+    let target = input.unwrap_or("whitelist.json");
+    let updated = patched(target, &original, existing, &delta)?;
+    let patch = unified_diff(target, &original, &updated);
+    let patch_path = Path::new(path).with_extension("patch");
+    std::fs::write(&patch_path, patch)
+        .with_context(|| format!("Failed to write patch '{}'", patch_path.display()))?;
+
+    println!(
+        "Suggested {} whitelist entries: {} (patch: {})",
+        count,
+        path,
+        patch_path.display()
+    );
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXISTING: &str = r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[{"domain":"api.github.com","port":443,"protocol":"TCP"}]}]}"#;
+
+    #[test]
+    fn delta_keeps_only_new_endpoints() {
+        let existing = WhitelistsJSON::from_json(EXISTING).unwrap();
+        let generated = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[
+                {"domain":"API.github.com","port":443,"protocol":"tcp"},
+                {"domain":"pypi.org","port":443,"protocol":"TCP"},
+                {"domain":"pypi.org","port":443,"protocol":"TCP"}]}]}"#,
+        )
+        .unwrap();
+        let delta = delta(&existing, &generated);
+        assert_eq!(delta.whitelists.len(), 1);
+        assert_eq!(delta.whitelists[0].endpoints.len(), 1);
+        assert_eq!(
+            delta.whitelists[0].endpoints[0].domain.as_deref(),
+            Some("pypi.org")
+        );
+    }
+
+    #[test]
+    fn patch_adds_only_suggested_lines() {
+        let existing = WhitelistsJSON::from_json(EXISTING).unwrap();
+        let options = WriteOptions {
+            omit_null: true,
+            date: None,
+        };
+        let original = existing.to_canonical_json(&options).unwrap();
+        let mut updated = existing.clone();
+        updated.augment(
+            &WhitelistsJSON::from_json(
+                r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[{"domain":"pypi.org","port":443,"protocol":"TCP"}]}]}"#,
+            )
+            .unwrap(),
+        );
+        let rendered = render("wl.json", &original, &updated).unwrap();
+        let patch = unified_diff("./wl.json", &original, &rendered);
+        assert!(patch.starts_with("--- a/wl.json\n+++ b/wl.json\n@@"));
+        assert!(patch.contains("+          \"domain\": \"pypi.org\","));
+        assert!(!patch.contains("-          \"domain\": \"api.github.com\""));
+    }
+
+    #[test]
+    fn patch_rewrites_non_canonical_json() {
+        let original = r#"{
+    "whitelists": [
+        {
+            "name": "custom_whitelist",
+            "endpoints": [
+                {
+                    "domain": "pypi.org",
+                    "as_number": 54113,
+                    "port": 443,
+                    "description": "null routes are fine"
+                },
+                {"as_owner": "GITHUB", "port": 443},
+                {"as_owner": "FASTLY", "port": 443}
+            ]
+        }
+    ],
+    "date": "July 28th 2025"
+}
+"#;
+        let delta = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[{"domain":"files.pythonhosted.org","port":443,"protocol":"TCP"}]}]}"#,
+        )
+        .unwrap();
+        let existing = WhitelistsJSON::from_json(original).unwrap();
+        let updated = patched("wl.json", original, existing.clone(), &delta).unwrap();
+        assert!(!updated.contains(": null"));
+
+        let mut expected = existing;
+        expected.augment(&delta);
+        expected.canonicalize();
+        assert_eq!(WhitelistsJSON::from_json(&updated).unwrap(), expected);
+    }
+
+    #[test]
+    fn patch_inserts_into_yaml_after_last_entry() {
+        let original = "\
+whitelists:
+- name: custom_whitelist
+  endpoints:
+  # checkout
+  - port: 443
+    domain: api.github.com
+  - domain: registry.npmjs.org
+    port: 443
+  extends: null
+date: d
+";
+        let delta = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[{"domain":"pypi.org","port":443,"protocol":"TCP"}]}]}"#,
+        )
+        .unwrap();
+        let existing = yaml::parse(original).unwrap().0;
+        let updated = patched("wl.yaml", original, existing, &delta).unwrap();
+        assert_eq!(
+            updated,
+            original.replace(
+                "    port: 443\n  extends",
+                "    port: 443\n  - domain: pypi.org\n    port: 443\n    protocol: TCP\n  extends"
+            )
+        );
+        let (parsed, _) = yaml::parse(&updated).unwrap();
+        assert_eq!(parsed.whitelists[0].endpoints.len(), 3);
+    }
+}
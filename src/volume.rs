@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use flodbadd::sessions::SessionInfo;
+
+use crate::explain::{trace_endpoint, Subject};
+use crate::policy::{is_egress, UNRESOLVED_DOMAINS};
+use crate::whitelist::{WhitelistEndpoint, CUSTOM_WHITELIST_NAME};
+
+/// Outbound bytes below which the outbound/inbound ratio is not checked:
+/// small uploads with no response are normal (telemetry, health checks).
+pub const RATIO_MIN_OUTBOUND_BYTES: u64 = 1_000_000;
+
+/// What a limit sums traffic over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Each destination domain, or IP when unresolved.
+    Destination,
+    /// Each local process.
+    Process,
+    /// All egress of the run.
+    Run,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Outbound bytes.
+    Bytes,
+    /// Outbound bytes per inbound byte.
+    Ratio,
+    /// Number of sessions.
+    Connections,
+}
+
+/// A threshold such as `destination.bytes=500MB`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub scope: Scope,
+    pub metric: Metric,
+    pub max: f64,
+}
+
+/// Egress volume thresholds checked by `scan` and `watch`.
+#[derive(Debug, Default, Clone)]
+pub struct VolumeLimits {
+    pub limits: Vec<Limit>,
+}
+
+/// A destination, process, whitelist entry or run over one of its limits.
+pub struct VolumeViolation {
+    /// What exceeded the limit, e.g. `destination api.example.com`.
+    pub scope: String,
+    /// The limit that was exceeded.
+    pub metric: Metric,
+    pub max: f64,
+    pub detail: String,
+    pub sessions: Vec<SessionInfo>,
+}
+
+impl VolumeViolation {
+    /// Identifies the violation across `watch` polls: the rule, what it sums
+    /// over and the limit.
+    pub fn key(&self) -> String {
+        format!("{}: {:?} over {}", self.scope, self.metric, self.max)
+    }
+}
+
+/// Parses a byte size such as `1048576`, `500MB`, `1.5GB` or `2GiB`. Units
+/// without `i` are decimal.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: f64 = amount
+        .parse()
+        .with_context(|| format!("Invalid size '{}'", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => bail!(
+            "Invalid size unit in '{}' (use B, KB, MB, GB, TB or KiB..TiB)",
+            value
+        ),
+    };
+    Ok((amount * multiplier as f64) as u64)
+}
+
+/// Parses `--egress-limit <scope>.<metric>=<value>`.
+pub fn parse_limit(value: &str) -> Result<Limit> {
+    let Some((key, max)) = value.split_once('=') else {
+        bail!("Invalid egress limit '{}' (use <scope>.<metric>=<value>, e.g. destination.bytes=500MB)", value);
+    };
+    let Some((scope, metric)) = key.trim().split_once('.') else {
+        bail!(
+            "Invalid egress limit '{}' (use <scope>.<metric>=<value>)",
+            value
+        );
+    };
+    let scope = match scope {
+        "destination" => Scope::Destination,
+        "process" => Scope::Process,
+        "run" => Scope::Run,
+        _ => bail!(
+            "Invalid egress limit scope '{}' (use destination, process or run)",
+            scope
+        ),
+    };
+    let (metric, max) = match metric {
+        "bytes" => (Metric::Bytes, parse_size(max)? as f64),
+        "ratio" => (
+            Metric::Ratio,
+            max.trim()
+                .parse::<f64>()
+                .with_context(|| format!("Invalid ratio in '{}'", value))?,
+        ),
+        "connections" => (
+            Metric::Connections,
+            max.trim()
+                .parse::<usize>()
+                .with_context(|| format!("Invalid connection count in '{}'", value))?
+                as f64,
+        ),
+        _ => bail!(
+            "Invalid egress limit metric '{}' (use bytes, ratio or connections)",
+            metric
+        ),
+    };
+    if max <= 0.0 {
+        bail!("Egress limit '{}' must be positive", value);
+    }
+    Ok(Limit { scope, metric, max })
+}
+
+/// `1234567` -> `1.2 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "kB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Traffic summed over a group of sessions.
+#[derive(Debug, Default, Clone)]
+struct Totals {
+    outbound: u64,
+    inbound: u64,
+    members: Vec<usize>,
+}
+
+impl Totals {
+    fn add(&mut self, index: usize, session: &SessionInfo) {
+        self.outbound += session.stats.outbound_bytes;
+        self.inbound += session.stats.inbound_bytes;
+        self.members.push(index);
+    }
+
+    fn observed(&self) -> String {
+        format!(
+            "outbound {}, inbound {}, {} connections",
+            format_bytes(self.outbound),
+            format_bytes(self.inbound),
+            self.members.len()
+        )
+    }
+
+    /// Why these totals break `limit`, or `None`.
+    fn exceeds(&self, metric: Metric, max: f64) -> Option<String> {
+        match metric {
+            Metric::Bytes if self.outbound as f64 > max => Some(format!(
+                "outbound bytes over {} ({})",
+                format_bytes(max as u64),
+                self.observed()
+            )),
+            Metric::Ratio if self.outbound >= RATIO_MIN_OUTBOUND_BYTES => {
+                let ratio = self.outbound as f64 / self.inbound.max(1) as f64;
+                (ratio > max).then(|| {
+                    format!(
+                        "outbound/inbound ratio {:.1} over {} ({})",
+                        ratio,
+                        max,
+                        self.observed()
+                    )
+                })
+            }
+            Metric::Connections if self.members.len() as f64 > max => {
+                Some(format!("connections over {} ({})", max, self.observed()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Destination a session's traffic is counted against.
+fn destination(session: &SessionInfo) -> String {
+    match session.dst_domain.as_deref() {
+        Some(domain) if !UNRESOLVED_DOMAINS.contains(&domain) && !domain.is_empty() => {
+            domain.to_string()
+        }
+        _ => session.session.dst_ip.to_string(),
+    }
+}
+
+fn process(session: &SessionInfo) -> String {
+    session
+        .l7
+        .as_ref()
+        .map(|l7| l7.process_name.clone())
+        .unwrap_or_else(|| "unknown process".to_string())
+}
+
+impl VolumeLimits {
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    /// Checks egress sessions against the limits and against the
+    /// `max_outbound_bytes` budgets of `budgeted` whitelist entries. A
+    /// session counts towards every budgeted entry it matches.
+    pub fn check(
+        &self,
+        sessions: &[SessionInfo],
+        budgeted: &[WhitelistEndpoint],
+    ) -> Vec<VolumeViolation> {
+        let egress: Vec<&SessionInfo> = sessions.iter().filter(|s| is_egress(s)).collect();
+        let mut groups: Vec<(String, Metric, f64, Totals)> = Vec::new();
+
+        for limit in &self.limits {
+            let mut totals: BTreeMap<String, Totals> = BTreeMap::new();
+            for (index, session) in egress.iter().enumerate() {
+                let key = match limit.scope {
+                    Scope::Destination => format!("destination {}", destination(session)),
+                    Scope::Process => format!("process {}", process(session)),
+                    Scope::Run => "run".to_string(),
+                };
+                totals.entry(key).or_default().add(index, session);
+            }
+            groups.extend(
+                totals
+                    .into_iter()
+                    .map(|(key, totals)| (key, limit.metric, limit.max, totals)),
+            );
+        }
+
+        let subjects: Vec<Subject> = if budgeted.is_empty() {
+            Vec::new()
+        } else {
+            egress.iter().map(|s| Subject::from_session(s)).collect()
+        };
+        for endpoint in budgeted {
+            let Some(max) = endpoint.max_outbound_bytes else {
+                continue;
+            };
+            let mut totals = Totals::default();
+            for (index, subject) in subjects.iter().enumerate() {
+                if trace_endpoint(CUSTOM_WHITELIST_NAME, endpoint, subject).matched {
+                    totals.add(index, egress[index]);
+                }
+            }
+            groups.push((
+                format!("whitelist entry {}", endpoint.label()),
+                Metric::Bytes,
+                max as f64,
+                totals,
+            ));
+        }
+
+        groups
+            .into_iter()
+            .filter_map(|(scope, metric, max, totals)| {
+                let detail = totals.exceeds(metric, max)?;
+                Some(VolumeViolation {
+                    scope,
+                    metric,
+                    max,
+                    detail,
+                    sessions: totals.members.iter().map(|&i| egress[i].clone()).collect(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Prints volume violations with their observed totals and returns the
+/// sessions involved.
+pub fn report(violations: Vec<VolumeViolation>) -> Vec<SessionInfo> {
+    if violations.is_empty() {
+        return Vec::new();
+    }
+    println!("Egress volume limits exceeded: {}", violations.len());
+    for violation in &violations {
+        println!("  - [volume] {}: {}", violation.scope, violation.detail);
+    }
+    violations.into_iter().flat_map(|v| v.sessions).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_and_limits() {
+        assert_eq!(parse_size("1048576").unwrap(), 1_048_576);
+        assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+        assert_eq!(parse_size("1.5gb").unwrap(), 1_500_000_000);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert!(parse_size("5 parsecs").is_err());
+
+        assert_eq!(
+            parse_limit("destination.bytes=500MB").unwrap(),
+            Limit {
+                scope: Scope::Destination,
+                metric: Metric::Bytes,
+                max: 500_000_000.0
+            }
+        );
+        assert_eq!(parse_limit("run.connections=200").unwrap().max, 200.0);
+        assert_eq!(
+            parse_limit("process.ratio=20").unwrap().metric,
+            Metric::Ratio
+        );
+        assert!(parse_limit("host.bytes=1MB").is_err());
+        assert!(parse_limit("run.bytes").is_err());
+        assert!(parse_limit("run.connections=0").is_err());
+    }
+
+    #[test]
+    fn totals_against_limits() {
+        let totals = Totals {
+            outbound: 600_000_000,
+            inbound: 2_000_000,
+            members: vec![0, 1, 2],
+        };
+        assert_eq!(
+            totals.exceeds(Metric::Bytes, 500_000_000.0).as_deref(),
+            Some("outbound bytes over 500.0 MB (outbound 600.0 MB, inbound 2.0 MB, 3 connections)")
+        );
+        assert!(totals.exceeds(Metric::Bytes, 1e9).is_none());
+        assert!(totals.exceeds(Metric::Ratio, 100.0).is_some());
+        assert!(totals.exceeds(Metric::Ratio, 500.0).is_none());
+        assert!(totals.exceeds(Metric::Connections, 2.0).is_some());
+
+        // Small uploads are not judged by their ratio.
+        let small = Totals {
+            outbound: 50_000,
+            inbound: 0,
+            members: vec![0],
+        };
+        assert!(small.exceeds(Metric::Ratio, 1.0).is_none());
+        assert_eq!(format_bytes(999), "999 B");
+    }
+
+    #[test]
+    fn violations_are_keyed_on_their_limit() {
+        let violation = |metric, max| VolumeViolation {
+            scope: "destination api.example.com".to_string(),
+            metric,
+            max,
+            detail: "outbound bytes over ...".to_string(),
+            sessions: Vec::new(),
+        };
+        let keys: std::collections::HashSet<String> = [
+            violation(Metric::Bytes, 500_000_000.0),
+            violation(Metric::Bytes, 1e9),
+            violation(Metric::Connections, 200.0),
+            violation(Metric::Bytes, 500_000_000.0),
+        ]
+        .iter()
+        .map(VolumeViolation::key)
+        .collect();
+        assert_eq!(keys.len(), 3);
+    }
+}
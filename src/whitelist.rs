@@ -0,0 +1,819 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Name of the whitelist that Flodbadd evaluates when a custom whitelist is loaded.
+pub const CUSTOM_WHITELIST_NAME: &str = "custom_whitelist";
+
+/// AS owners whose address space is shared by many unrelated domains. An IP
+/// match against one of these providers says nothing about the destination.
+const CDN_OWNERS: &[&str] = &[
+    "fastly",
+    "cloudflare",
+    "amazon",
+    "aws",
+    "google",
+    "microsoft",
+    "azure",
+    "akamai",
+];
+
+/// How whitelist files are written by `create-whitelist` and `whitelist` commands.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Leave out `null` fields instead of writing them explicitly.
+    pub omit_null: bool,
+    /// Replaces the `date` field; an empty string removes the run date from
+    /// the output so that identical traffic yields identical files.
+    pub date: Option<String>,
+}
+
+/// Local mirror of the whitelist JSON format shared with EDAMAME Posture.
+///
+/// Flodbadd owns the authoritative model; this copy exists so the CLI can
+/// inspect and rewrite whitelist files without a running capture.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WhitelistsJSON {
+    pub date: String,
+    #[serde(default)]
+    pub signature: Option<String>,
+    pub whitelists: Vec<WhitelistInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WhitelistInfo {
+    pub name: String,
+    #[serde(default)]
+    pub extends: Option<Vec<String>>,
+    pub endpoints: Vec<WhitelistEndpoint>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct WhitelistEndpoint {
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub protocol: Option<String>,
+    #[serde(default)]
+    pub as_number: Option<u32>,
+    #[serde(default)]
+    pub as_country: Option<String>,
+    #[serde(default)]
+    pub as_owner: Option<String>,
+    #[serde(default)]
+    pub process: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Why the destination is allowed. Required for hand-added entries in
+    /// strict mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub justification: Option<String>,
+    /// Team or person accountable for the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Date (`YYYY-MM-DD`, valid through that day, UTC) or RFC 3339 timestamp
+    /// after which the entry stops matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    /// Outbound bytes per run allowed to the destination, summed over the
+    /// sessions matching the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_outbound_bytes: Option<u64>,
+    /// Written by `create-whitelist` or `--generalize` rather than by hand,
+    /// which exempts the entry from the justification of strict mode.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub generated: bool,
+}
+
+/// Where `scan` and `watch` load the custom whitelist from.
+#[derive(Debug, Clone, Default)]
+pub struct WhitelistSource {
+    pub path: Option<String>,
+    /// Temporary exceptions (see `flodviddar allow`) layered on top of the
+    /// custom whitelist.
+    pub exceptions: Option<String>,
+    /// Built-in presets (see `crate::presets`) the custom whitelist extends.
+    pub presets: Vec<String>,
+    /// Reject hand-added entries without a justification.
+    pub strict: bool,
+}
+
+/// A custom whitelist prepared for enforcement by `scan` and `watch`.
+pub struct EnforcedWhitelist {
+    /// JSON handed to Flodbadd, without expired entries or the
+    /// Flodviddar-only `justification`/`owner`/`expires`/`max_outbound_bytes`
+    /// fields.
+    pub json: String,
+    pub expired: Vec<WhitelistEndpoint>,
+    /// Entries in force that carry a `max_outbound_bytes` budget.
+    pub budgeted: Vec<WhitelistEndpoint>,
+    pub expired_exceptions: Vec<WhitelistEndpoint>,
+    /// Earliest expiry among the entries still in force.
+    pub next_expiry: Option<DateTime<Utc>>,
+}
+
+/// Reads a whitelist file as the JSON expected by Flodbadd. JSON files are
+/// passed through untouched; YAML files are converted.
+pub fn load_engine_json(path: &str) -> Result<String> {
+    if crate::yaml::is_yaml_path(path) {
+        return Ok(serde_json::to_string(&WhitelistsJSON::load(path)?)?);
+    }
+    std::fs::read_to_string(path).with_context(|| format!("Failed to read whitelist '{}'", path))
+}
+
+fn existing(path: &Option<String>, what: &str) -> Option<String> {
+    let path = path.as_ref()?;
+    if std::path::Path::new(path).exists() {
+        Some(path.clone())
+    } else {
+        eprintln!("{} file '{}' not found, ignoring", what, path);
+        None
+    }
+}
+
+impl WhitelistSource {
+    /// Loads the custom whitelist with its exceptions layered on top and the
+    /// selected presets underneath, drops expired entries and, in strict
+    /// mode, rejects the whitelist when a hand-added entry has no
+    /// justification. Returns `None` when there is nothing to load.
+    /// Exceptions without a custom whitelist or preset are ignored: on their
+    /// own they would turn an unrestricted run into one that allows only
+    /// the exceptions.
+    pub fn load(&self) -> Result<Option<EnforcedWhitelist>> {
+        let path = existing(&self.path, "Custom whitelist");
+        let exceptions = existing(&self.exceptions, "Exceptions");
+        if path.is_none() && self.presets.is_empty() {
+            if let Some(exceptions) = &exceptions {
+                eprintln!(
+                    "Warning: exceptions file '{}' ignored, no custom whitelist or preset to layer it on",
+                    exceptions
+                );
+            }
+            return Ok(None);
+        }
+        let now = Utc::now();
+
+        let mut whitelists = match &path {
+            Some(path) => WhitelistsJSON::load(path)?,
+            None => WhitelistsJSON::empty(),
+        };
+        let mut expired_exceptions = Vec::new();
+        if let Some(exceptions) = &exceptions {
+            let mut layered = Vec::new();
+            for info in WhitelistsJSON::load(exceptions)?.whitelists {
+                for endpoint in info.endpoints {
+                    match endpoint.expires_at()? {
+                        Some(at) if at <= now => expired_exceptions.push(endpoint),
+                        _ => layered.push(endpoint),
+                    }
+                }
+            }
+            whitelists.custom_whitelist_mut().endpoints.extend(layered);
+        }
+        crate::presets::layer(&mut whitelists, &self.presets)?;
+
+        let mut enforced = whitelists
+            .enforce(self.strict, now)
+            .with_context(|| format!("Whitelist '{}' rejected", path.as_deref().unwrap_or("-")))?;
+        enforced.expired_exceptions = expired_exceptions;
+        Ok(Some(enforced))
+    }
+}
+
+impl EnforcedWhitelist {
+    /// Reports entries that stopped matching because they expired. Expired
+    /// exceptions are warnings: they are meant to be removed or renewed.
+    pub fn print_expired(&self) {
+        if !self.expired.is_empty() {
+            println!(
+                "Expired whitelist entries (no longer matching): {}",
+                self.expired.len()
+            );
+            for endpoint in &self.expired {
+                println!(
+                    "  - {} expired {} (owner: {})",
+                    endpoint.label(),
+                    endpoint.expires.as_deref().unwrap_or("?"),
+                    endpoint.owner.as_deref().unwrap_or("unknown")
+                );
+            }
+        }
+        for endpoint in &self.expired_exceptions {
+            eprintln!(
+                "Warning: exception {} expired {} (reason: {}, owner: {})",
+                endpoint.label(),
+                endpoint.expires.as_deref().unwrap_or("?"),
+                endpoint.justification.as_deref().unwrap_or("none"),
+                endpoint.owner.as_deref().unwrap_or("unknown")
+            );
+        }
+    }
+}
+
+impl WhitelistsJSON {
+    /// Loads a whitelist file, JSON or YAML depending on its extension.
+    pub fn load(path: &str) -> Result<Self> {
+        if crate::yaml::is_yaml_path(path) {
+            return Ok(crate::yaml::load(path)?.0);
+        }
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read whitelist '{}'", path))?;
+        Self::from_json(&json).with_context(|| format!("Failed to parse whitelist '{}'", path))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// A whitelist file holding an empty custom whitelist.
+    pub fn empty() -> Self {
+        WhitelistsJSON {
+            date: Utc::now().format("%B %-d %Y").to_string(),
+            signature: None,
+            whitelists: vec![WhitelistInfo {
+                name: CUSTOM_WHITELIST_NAME.to_string(),
+                extends: None,
+                endpoints: Vec::new(),
+            }],
+        }
+    }
+
+    /// The custom whitelist, created if the file does not have one.
+    pub fn custom_whitelist_mut(&mut self) -> &mut WhitelistInfo {
+        let index = match self
+            .whitelists
+            .iter()
+            .position(|w| w.name == CUSTOM_WHITELIST_NAME)
+        {
+            Some(index) => index,
+            None => {
+                self.whitelists.push(WhitelistInfo {
+                    name: CUSTOM_WHITELIST_NAME.to_string(),
+                    extends: None,
+                    endpoints: Vec::new(),
+                });
+                self.whitelists.len() - 1
+            }
+        };
+        &mut self.whitelists[index]
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WhitelistInfo> {
+        self.whitelists.iter().find(|w| w.name == name)
+    }
+
+    /// Puts the whitelists in a canonical order: whitelists by name, sorted
+    /// `extends`, and endpoints by every field, starting with (domain, IP,
+    /// port, protocol, ASN, AS country, AS owner, process) with IPs compared
+    /// numerically. Only exact duplicate endpoints are removed.
+    pub fn canonicalize(&mut self) {
+        self.whitelists.sort_by(|a, b| a.name.cmp(&b.name));
+        for info in &mut self.whitelists {
+            if let Some(extends) = &mut info.extends {
+                extends.sort();
+                extends.dedup();
+            }
+            info.endpoints.sort_by_cached_key(|e| e.sort_key());
+            info.endpoints.dedup();
+        }
+    }
+
+    /// Serializes a canonicalized copy as pretty-printed JSON with sorted keys
+    /// and a trailing newline, so that consecutive runs diff cleanly.
+    pub fn to_canonical_json(&self, options: &WriteOptions) -> Result<String> {
+        let mut canonical = self.clone();
+        canonical.canonicalize();
+        if let Some(date) = &options.date {
+            canonical.date = date.clone();
+        }
+        // `Value` maps are ordered by key, which gives a stable field order.
+        let mut value = serde_json::to_value(&canonical)?;
+        if options.omit_null {
+            strip_nulls(&mut value);
+        }
+        let mut json = serde_json::to_string_pretty(&value)?;
+        json.push('\n');
+        Ok(json)
+    }
+
+    /// Prepares the whitelist for the engine as of `now`. See
+    /// [`WhitelistSource::load`].
+    pub fn enforce(mut self, strict: bool, now: DateTime<Utc>) -> Result<EnforcedWhitelist> {
+        if strict {
+            let missing: Vec<String> = self
+                .whitelists
+                .iter()
+                .flat_map(|w| w.endpoints.iter())
+                .filter(|e| !e.generated && e.justification.is_none())
+                .map(|e| e.label())
+                .collect();
+            if !missing.is_empty() {
+                bail!(
+                    "strict mode: {} hand-added endpoints lack a justification:\n  - {}",
+                    missing.len(),
+                    missing.join("\n  - ")
+                );
+            }
+        }
+
+        let mut expired = Vec::new();
+        let mut budgeted = Vec::new();
+        let mut next_expiry: Option<DateTime<Utc>> = None;
+        for info in &mut self.whitelists {
+            let mut kept = Vec::with_capacity(info.endpoints.len());
+            for mut endpoint in info.endpoints.drain(..) {
+                match endpoint.expires_at()? {
+                    Some(at) if at <= now => {
+                        expired.push(endpoint);
+                        continue;
+                    }
+                    Some(at) => {
+                        next_expiry = Some(next_expiry.map_or(at, |n| n.min(at)));
+                    }
+                    None => {}
+                }
+                if endpoint.max_outbound_bytes.is_some() {
+                    budgeted.push(endpoint.clone());
+                }
+                endpoint.justification = None;
+                endpoint.owner = None;
+                endpoint.expires = None;
+                endpoint.max_outbound_bytes = None;
+                endpoint.generated = false;
+                kept.push(endpoint);
+            }
+            info.endpoints = kept;
+        }
+
+        Ok(EnforcedWhitelist {
+            json: serde_json::to_string(&self)?,
+            expired,
+            budgeted,
+            expired_exceptions: Vec::new(),
+            next_expiry,
+        })
+    }
+
+    /// Copies `justification`, `owner`, `expires`, `max_outbound_bytes` and
+    /// `generated` from the endpoints of `from` with the same fingerprint.
+    /// Used after the engine rewrote a whitelist, since Flodbadd does not
+    /// know these fields.
+    pub fn restore_metadata(&mut self, from: &WhitelistsJSON) {
+        let metadata: HashMap<EndpointFingerprint, &WhitelistEndpoint> = from
+            .whitelists
+            .iter()
+            .flat_map(|w| w.endpoints.iter())
+            .map(|e| (e.fingerprint(), e))
+            .collect();
+        for endpoint in self
+            .whitelists
+            .iter_mut()
+            .flat_map(|w| w.endpoints.iter_mut())
+        {
+            if let Some(source) = metadata.get(&endpoint.fingerprint()) {
+                endpoint.justification = endpoint
+                    .justification
+                    .take()
+                    .or_else(|| source.justification.clone());
+                endpoint.owner = endpoint.owner.take().or_else(|| source.owner.clone());
+                endpoint.expires = endpoint.expires.take().or_else(|| source.expires.clone());
+                endpoint.max_outbound_bytes =
+                    endpoint.max_outbound_bytes.or(source.max_outbound_bytes);
+                endpoint.generated = source.generated;
+            }
+        }
+    }
+
+    /// Marks every endpoint as written by whitelist generation.
+    pub fn mark_generated(&mut self) {
+        for endpoint in self
+            .whitelists
+            .iter_mut()
+            .flat_map(|w| w.endpoints.iter_mut())
+        {
+            endpoint.generated = true;
+        }
+    }
+
+    /// Adds the whitelists and endpoints of `other` that are not already
+    /// present, keeping existing entries (and their order) untouched.
+    /// Returns the number of endpoints added.
+    pub fn augment(&mut self, other: &WhitelistsJSON) -> usize {
+        let mut added = 0;
+        for info in &other.whitelists {
+            match self.whitelists.iter_mut().find(|w| w.name == info.name) {
+                Some(existing) => {
+                    let mut seen: HashSet<EndpointFingerprint> =
+                        existing.endpoints.iter().map(|e| e.fingerprint()).collect();
+                    for endpoint in &info.endpoints {
+                        if seen.insert(endpoint.fingerprint()) {
+                            existing.endpoints.push(endpoint.clone());
+                            added += 1;
+                        }
+                    }
+                }
+                None => {
+                    added += info.endpoints.len();
+                    self.whitelists.push(info.clone());
+                }
+            }
+        }
+        added
+    }
+
+    /// Returns the endpoints of `name` followed by those of every whitelist it
+    /// extends, tagged with the whitelist they were declared in. Cycles are
+    /// broken by visiting each whitelist once.
+    pub fn resolve_endpoints(&self, name: &str) -> Vec<(&str, &WhitelistEndpoint)> {
+        let mut visited: Vec<&str> = Vec::new();
+        let mut out = Vec::new();
+        self.collect_endpoints(name, &mut visited, &mut out);
+        out
+    }
+
+    fn collect_endpoints<'a>(
+        &'a self,
+        name: &str,
+        visited: &mut Vec<&'a str>,
+        out: &mut Vec<(&'a str, &'a WhitelistEndpoint)>,
+    ) {
+        let Some(info) = self.get(name) else {
+            return;
+        };
+        if visited.contains(&info.name.as_str()) {
+            return;
+        }
+        visited.push(&info.name);
+        out.extend(info.endpoints.iter().map(|e| (info.name.as_str(), e)));
+        for parent in info.extends.iter().flatten() {
+            self.collect_endpoints(parent, visited, out);
+        }
+    }
+}
+
+/// Identity of an endpoint for deduplication, as fingerprinted by the engine:
+/// (domain, IP, port, protocol, ASN, process).
+pub type EndpointFingerprint = (
+    Option<String>,
+    Option<String>,
+    Option<u16>,
+    Option<String>,
+    Option<u32>,
+    Option<String>,
+);
+
+/// Every field of an endpoint: the matching fields first, then the metadata
+/// and the original spelling of case-folded fields, so that only equal
+/// endpoints compare equal.
+type EndpointSortKey = (
+    (
+        Option<String>,
+        Option<(Option<IpAddr>, String)>,
+        Option<u16>,
+        Option<String>,
+        Option<u32>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    ),
+    (
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<u64>,
+        Option<String>,
+        Option<String>,
+        bool,
+    ),
+);
+
+impl WhitelistEndpoint {
+    fn sort_key(&self) -> EndpointSortKey {
+        let (domain, _, port, protocol, as_number, process) = self.fingerprint();
+        (
+            (
+                domain,
+                self.ip.as_ref().map(|ip| {
+                    let address = ip.split('/').next().unwrap_or(ip);
+                    (address.parse::<IpAddr>().ok(), ip.clone())
+                }),
+                port,
+                protocol,
+                as_number,
+                self.as_country.clone(),
+                self.as_owner.clone(),
+                process,
+            ),
+            (
+                self.description.clone(),
+                self.justification.clone(),
+                self.owner.clone(),
+                self.expires.clone(),
+                self.max_outbound_bytes,
+                self.domain.clone(),
+                self.protocol.clone(),
+                self.generated,
+            ),
+        )
+    }
+
+    pub fn expires_at(&self) -> Result<Option<DateTime<Utc>>> {
+        let Some(expires) = &self.expires else {
+            return Ok(None);
+        };
+        if let Ok(date) = NaiveDate::parse_from_str(expires, "%Y-%m-%d") {
+            let end_of_day = date
+                .succ_opt()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .ok_or_else(|| anyhow!("Invalid expiry date '{}'", expires))?;
+            return Ok(Some(end_of_day.and_utc()));
+        }
+        DateTime::parse_from_rfc3339(expires)
+            .map(|at| Some(at.with_timezone(&Utc)))
+            .with_context(|| {
+                format!(
+                    "Invalid expires '{}' on {} (use YYYY-MM-DD or RFC 3339)",
+                    expires,
+                    self.label()
+                )
+            })
+    }
+
+    pub fn fingerprint(&self) -> EndpointFingerprint {
+        (
+            self.domain.as_ref().map(|d| d.to_ascii_lowercase()),
+            self.ip.clone(),
+            self.port,
+            self.protocol.as_ref().map(|p| p.to_ascii_uppercase()),
+            self.as_number,
+            self.process.clone(),
+        )
+    }
+
+    /// Short `domain|ip:port/protocol` label used in CLI output.
+    pub fn label(&self) -> String {
+        let host = self
+            .domain
+            .as_deref()
+            .or(self.ip.as_deref())
+            .or(self.as_owner.as_deref())
+            .unwrap_or("*");
+        let port = self
+            .port
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "*".to_string());
+        let protocol = self.protocol.as_deref().unwrap_or("*");
+        match &self.process {
+            Some(process) => format!("{}:{}/{} [{}]", host, port, protocol, process),
+            None => format!("{}:{}/{}", host, port, protocol),
+        }
+    }
+}
+
+fn strip_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Matches a domain against a whitelist pattern using the engine's wildcard
+/// rules: `*.example.com` (subdomains only), `example.*` (any TLD) and
+/// `api.*.example.com` (exactly one label).
+pub fn domain_matches(pattern: &str, domain: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        return domain.len() > suffix.len() + 1
+            && domain.ends_with(suffix)
+            && domain.as_bytes()[domain.len() - suffix.len() - 1] == b'.';
+    }
+    if let Some(prefix) = pattern.strip_suffix(".*") {
+        return domain.len() > prefix.len() + 1
+            && domain.starts_with(prefix)
+            && domain.as_bytes()[prefix.len()] == b'.';
+    }
+    if pattern.contains('*') {
+        let pattern_labels: Vec<&str> = pattern.split('.').collect();
+        let domain_labels: Vec<&str> = domain.split('.').collect();
+        return pattern_labels.len() == domain_labels.len()
+            && pattern_labels
+                .iter()
+                .zip(domain_labels.iter())
+                .all(|(p, d)| *p == "*" || p == d);
+    }
+    pattern == domain
+}
+
+/// Matches an IP against a whitelist entry that is either a single address or
+/// a CIDR block.
+pub fn ip_matches(pattern: &str, ip: &IpAddr) -> bool {
+    let Some((network, prefix)) = pattern.split_once('/') else {
+        return pattern.parse::<IpAddr>().map(|p| p == *ip).unwrap_or(false);
+    };
+    let (Ok(network), Ok(prefix)) = (network.parse::<IpAddr>(), prefix.parse::<u32>()) else {
+        return false;
+    };
+    match (network, ip) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(net) & mask == u32::from(*ip) & mask
+        }
+        (IpAddr::V6(net), IpAddr::V6(ip)) if prefix <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(net) & mask == u128::from(*ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Whether an AS owner belongs to one of the CDN providers guarded by the engine.
+pub fn is_cdn_owner(owner: &str) -> bool {
+    let owner = owner.to_ascii_lowercase();
+    CDN_OWNERS.iter().any(|cdn| owner.contains(cdn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_domains() {
+        assert!(domain_matches("*.github.com", "api.github.com"));
+        assert!(!domain_matches("*.github.com", "github.com"));
+        assert!(!domain_matches("*.github.com", "evilgithub.com"));
+        assert!(domain_matches("example.*", "example.org"));
+        assert!(domain_matches("api.*.example.com", "api.eu.example.com"));
+        assert!(!domain_matches("api.*.example.com", "api.a.b.example.com"));
+        assert!(domain_matches("GitHub.com.", "github.com"));
+    }
+
+    #[test]
+    fn cidr_matching() {
+        let ip: IpAddr = "140.82.114.3".parse().unwrap();
+        assert!(ip_matches("140.82.112.0/20", &ip));
+        assert!(ip_matches("140.82.114.3", &ip));
+        assert!(!ip_matches("140.82.0.0/24", &ip));
+        let ip6: IpAddr = "2606:50c0:8000::154".parse().unwrap();
+        assert!(ip_matches("2606:50c0::/32", &ip6));
+        assert!(!ip_matches("140.82.112.0/20", &ip6));
+    }
+
+    #[test]
+    fn augment_skips_duplicates() {
+        let mut base = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[{"domain":"a.com","port":443,"protocol":"TCP"}]}]}"#,
+        )
+        .unwrap();
+        let other = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[{"domain":"A.com","port":443,"protocol":"tcp"},{"domain":"b.com","port":443,"protocol":"TCP"}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(base.augment(&other), 1);
+        assert_eq!(base.whitelists[0].endpoints.len(), 2);
+    }
+
+    #[test]
+    fn canonical_output_is_order_independent() {
+        let a = WhitelistsJSON::from_json(
+            r#"{"date":"July 28th 2025","whitelists":[{"name":"custom_whitelist","endpoints":[
+                {"ip":"10.0.0.10","port":443},{"domain":"b.com","port":443},{"ip":"10.0.0.9","port":443}]}]}"#,
+        )
+        .unwrap();
+        let mut b = a.clone();
+        b.whitelists[0].endpoints.reverse();
+        b.date = "July 29th 2025".to_string();
+
+        let options = WriteOptions {
+            omit_null: true,
+            date: Some(String::new()),
+        };
+        let json = a.to_canonical_json(&options).unwrap();
+        assert_eq!(json, b.to_canonical_json(&options).unwrap());
+        assert!(!json.contains("null"));
+        // Numeric, not lexicographic, IP order
+        assert!(json.find("10.0.0.9").unwrap() < json.find("10.0.0.10").unwrap());
+    }
+
+    #[test]
+    fn canonical_output_keeps_entries_differing_in_metadata() {
+        let a = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[
+                {"as_owner":"GITHUB","port":443},{"as_owner":"FASTLY","port":443},
+                {"domain":"a.com","port":443,"description":"x"},{"domain":"a.com","port":443,"description":"y"},
+                {"as_owner":"GITHUB","port":443}]}]}"#,
+        )
+        .unwrap();
+        let mut b = a.clone();
+        b.whitelists[0].endpoints.reverse();
+
+        let mut canonical = a.clone();
+        canonical.canonicalize();
+        let owners: Vec<Option<&str>> = canonical.whitelists[0]
+            .endpoints
+            .iter()
+            .map(|e| e.as_owner.as_deref())
+            .collect();
+        assert_eq!(
+            owners,
+            vec![Some("FASTLY"), Some("GITHUB"), None, None],
+            "only the exact duplicate is removed"
+        );
+        let options = WriteOptions::default();
+        assert_eq!(
+            a.to_canonical_json(&options).unwrap(),
+            b.to_canonical_json(&options).unwrap()
+        );
+    }
+
+    #[test]
+    fn expired_entries_stop_matching() {
+        let wl = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[
+                {"domain":"old.example.com","justification":"migration","owner":"infra","expires":"2025-06-30"},
+                {"domain":"new.example.com","justification":"tests","expires":"2025-07-31T12:00:00Z"},
+                {"domain":"api.github.com","justification":"checkout","max_outbound_bytes":50000000}]}]}"#,
+        )
+        .unwrap();
+        let now = DateTime::parse_from_rfc3339("2025-07-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let enforced = wl.enforce(true, now).unwrap();
+        assert_eq!(enforced.expired.len(), 1);
+        assert_eq!(
+            enforced.expired[0].domain.as_deref(),
+            Some("old.example.com")
+        );
+        assert!(!enforced.json.contains("old.example.com"));
+        assert!(!enforced.json.contains("justification"));
+        assert!(!enforced.json.contains("max_outbound_bytes"));
+        assert_eq!(enforced.budgeted.len(), 1);
+        assert_eq!(enforced.budgeted[0].max_outbound_bytes, Some(50_000_000));
+        assert_eq!(
+            enforced.next_expiry.map(|t| t.to_rfc3339()),
+            Some("2025-07-31T12:00:00+00:00".to_string())
+        );
+    }
+
+    #[test]
+    fn strict_mode_requires_justification_on_hand_added_entries() {
+        let wl = WhitelistsJSON::from_json(
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[
+                {"ip":"1.2.3.4","port":443,"description":"Auto-generated from session: a -> b","generated":true},
+                {"ip":"5.6.7.8","port":443,"description":"Auto-generated from session: c -> d"},
+                {"domain":"pastebin.com","port":443}]}]}"#,
+        )
+        .unwrap();
+        let err = wl.clone().enforce(true, Utc::now()).err().unwrap();
+        assert!(err.to_string().contains("pastebin.com"));
+        // Only the explicit marker exempts an entry, not its description.
+        assert!(err.to_string().contains("5.6.7.8"));
+        assert!(!err.to_string().contains("1.2.3.4"));
+        assert!(wl.enforce(false, Utc::now()).is_ok());
+    }
+
+    #[test]
+    fn exceptions_need_a_whitelist_to_layer_on() {
+        let path =
+            std::env::temp_dir().join(format!("flodviddar-exceptions-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"date":"d","whitelists":[{"name":"custom_whitelist","endpoints":[{"domain":"a.com","port":443,"protocol":"TCP"}]}]}"#,
+        )
+        .unwrap();
+        let mut source = WhitelistSource {
+            exceptions: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        assert!(source.load().unwrap().is_none());
+        source.presets = vec!["pypi".to_string()];
+        let enforced = source.load().unwrap().unwrap();
+        assert!(enforced.json.contains("a.com"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn extends_are_resolved_once() {
+        let json = r#"{"date":"today","whitelists":[
+            {"name":"custom_whitelist","extends":["base"],"endpoints":[{"domain":"a.com"}]},
+            {"name":"base","extends":["custom_whitelist"],"endpoints":[{"domain":"b.com"}]}
+        ]}"#;
+        let wl = WhitelistsJSON::from_json(json).unwrap();
+        let endpoints = wl.resolve_endpoints(CUSTOM_WHITELIST_NAME);
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[1].0, "base");
+    }
+}
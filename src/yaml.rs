@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+
+use crate::whitelist::{EndpointFingerprint, WhitelistEndpoint, WhitelistsJSON, WriteOptions};
+
+/// Whether a path names a YAML whitelist (`.yaml` / `.yml`).
+pub fn is_yaml_path(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    lower.ends_with(".yaml") || lower.ends_with(".yml")
+}
+
+/// Full-line comments found in a YAML whitelist, keyed by what they precede.
+///
+/// Comments are attached to the endpoint (by fingerprint) or whitelist (by
+/// name) that follows them, so they survive re-sorting and augmentation.
+/// Inline comments after a value are not preserved.
+#[derive(Debug, Default, Clone)]
+pub struct Comments {
+    pub header: Vec<String>,
+    pub whitelists: HashMap<String, Vec<String>>,
+    pub endpoints: HashMap<EndpointFingerprint, Vec<String>>,
+}
+
+pub fn parse(text: &str) -> Result<(WhitelistsJSON, Comments)> {
+    let whitelists: WhitelistsJSON = serde_norway::from_str(text)?;
+    let comments = extract_comments(text, &whitelists);
+    Ok((whitelists, comments))
+}
+
+pub fn load(path: &str) -> Result<(WhitelistsJSON, Comments)> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read whitelist '{}'", path))?;
+    parse(&text).with_context(|| format!("Failed to parse YAML whitelist '{}'", path))
+}
+
+fn comment_text(trimmed: &str) -> String {
+    let body = trimmed.trim_start_matches('#');
+    body.strip_prefix(' ')
+        .unwrap_or(body)
+        .trim_end()
+        .to_string()
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Walks the YAML text in step with the parsed document and attaches each
+/// block of full-line comments to the item it precedes. Documents written in
+/// flow style are left without comments.
+fn extract_comments(text: &str, whitelists: &WhitelistsJSON) -> Comments {
+    let mut comments = Comments::default();
+    let names: Vec<&str> = whitelists
+        .whitelists
+        .iter()
+        .map(|w| w.name.as_str())
+        .collect();
+    let endpoints: Vec<EndpointFingerprint> = whitelists
+        .whitelists
+        .iter()
+        .flat_map(|w| w.endpoints.iter().map(|e| e.fingerprint()))
+        .collect();
+
+    let mut pending: Vec<String> = Vec::new();
+    let mut seen_content = false;
+    let mut whitelists_indent: Option<usize> = None;
+    let mut whitelist_item_indent: Option<usize> = None;
+    let mut endpoints_indent: Option<usize> = None;
+    let mut endpoint_item_indent: Option<usize> = None;
+    let mut whitelist_index = 0;
+    let mut endpoint_index = 0;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            pending.push(comment_text(trimmed));
+            continue;
+        }
+        let indent = indent_of(line);
+        if !seen_content {
+            seen_content = true;
+            comments.header = std::mem::take(&mut pending);
+        }
+
+        if let Some(block) = endpoints_indent {
+            let is_item = trimmed.starts_with("- ") || trimmed == "-";
+            if indent < block || (indent == block && !is_item) {
+                endpoints_indent = None;
+                endpoint_item_indent = None;
+            } else if is_item && *endpoint_item_indent.get_or_insert(indent) == indent {
+                if let Some(fingerprint) = endpoints.get(endpoint_index) {
+                    if !pending.is_empty() {
+                        comments
+                            .endpoints
+                            .entry(fingerprint.clone())
+                            .or_default()
+                            .append(&mut pending);
+                    }
+                }
+                endpoint_index += 1;
+                continue;
+            }
+        }
+
+        if let Some(block) = whitelists_indent {
+            let is_item = trimmed.starts_with("- ") || trimmed == "-";
+            if indent < block || (indent == block && !is_item) {
+                whitelists_indent = None;
+            } else if is_item && *whitelist_item_indent.get_or_insert(indent) == indent {
+                if let Some(name) = names.get(whitelist_index) {
+                    if !pending.is_empty() {
+                        comments
+                            .whitelists
+                            .entry(name.to_string())
+                            .or_default()
+                            .append(&mut pending);
+                    }
+                }
+                whitelist_index += 1;
+            }
+        }
+
+        let key = trimmed.trim_start_matches("- ");
+        if key.starts_with("whitelists:") {
+            whitelists_indent = Some(indent);
+        } else if key.starts_with("endpoints:") {
+            endpoints_indent = Some(indent);
+            endpoint_item_indent = None;
+        }
+        pending.clear();
+    }
+
+    if endpoint_index != endpoints.len() || whitelist_index != names.len() {
+        tracing::warn!("YAML whitelist layout not recognized; comments will not be preserved");
+        return Comments::default();
+    }
+    comments
+}
+
+/// Renders a string as a YAML scalar: plain when unambiguous, otherwise as a
+/// double-quoted (JSON-compatible) string.
+fn scalar(value: &str) -> String {
+    const RESERVED: &[&str] = &[
+        "true", "false", "yes", "no", "on", "off", "null", "y", "n", "~",
+    ];
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .next()
+            .map(|c| c.is_ascii_alphabetic())
+            .unwrap_or(false)
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        && !RESERVED.contains(&value.to_ascii_lowercase().as_str());
+    if plain {
+        value.to_string()
+    } else {
+        serde_json::to_string(value).unwrap_or_default()
+    }
+}
+
+fn push_comments(out: &mut String, indent: usize, lines: Option<&Vec<String>>) {
+    for line in lines.into_iter().flatten() {
+        if line.is_empty() {
+            out.push_str(&format!("{:indent$}#\n", "", indent = indent));
+        } else {
+            out.push_str(&format!("{:indent$}# {}\n", "", line, indent = indent));
+        }
+    }
+}
+
+fn push_field(out: &mut String, first: &mut bool, indent: usize, key: &str, value: Option<String>) {
+    let prefix = if *first {
+        format!("{:indent$}- ", "", indent = indent)
+    } else {
+        format!("{:indent$}", "", indent = indent + 2)
+    };
+    *first = false;
+    out.push_str(&format!(
+        "{}{}: {}\n",
+        prefix,
+        key,
+        value.unwrap_or_else(|| "null".to_string())
+    ));
+}
+
+fn endpoint_fields(endpoint: &WhitelistEndpoint) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        ("domain", endpoint.domain.as_deref().map(scalar)),
+        ("ip", endpoint.ip.as_deref().map(scalar)),
+        ("port", endpoint.port.map(|p| p.to_string())),
+        ("protocol", endpoint.protocol.as_deref().map(scalar)),
+        ("as_number", endpoint.as_number.map(|n| n.to_string())),
+        ("as_country", endpoint.as_country.as_deref().map(scalar)),
+        ("as_owner", endpoint.as_owner.as_deref().map(scalar)),
+        ("process", endpoint.process.as_deref().map(scalar)),
+        ("description", endpoint.description.as_deref().map(scalar)),
+    ]
+}
+
+/// Flodviddar-only fields, written only when set.
+fn metadata_fields(endpoint: &WhitelistEndpoint) -> Vec<(&'static str, String)> {
+    [
+        (
+            "justification",
+            endpoint.justification.as_deref().map(scalar),
+        ),
+        ("owner", endpoint.owner.as_deref().map(scalar)),
+        ("expires", endpoint.expires.as_deref().map(scalar)),
+        (
+            "max_outbound_bytes",
+            endpoint.max_outbound_bytes.map(|b| b.to_string()),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|v| (key, v)))
+    .collect()
+}
+
+/// Writes the canonical YAML form of a whitelist, re-attaching `comments`.
+pub fn to_yaml(whitelists: &WhitelistsJSON, comments: &Comments, options: &WriteOptions) -> String {
+    let mut canonical = whitelists.clone();
+    canonical.canonicalize();
+    if let Some(date) = &options.date {
+        canonical.date = date.clone();
+    }
+
+    let mut out = String::new();
+    push_comments(&mut out, 0, Some(&comments.header));
+    if !comments.header.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&format!("date: {}\n", scalar(&canonical.date)));
+    if canonical.signature.is_some() || !options.omit_null {
+        out.push_str(&format!(
+            "signature: {}\n",
+            canonical
+                .signature
+                .as_deref()
+                .map(scalar)
+                .unwrap_or_else(|| "null".to_string())
+        ));
+    }
+    out.push_str("whitelists:\n");
+    for info in &canonical.whitelists {
+        push_comments(&mut out, 2, comments.whitelists.get(&info.name));
+        out.push_str(&format!("  - name: {}\n", scalar(&info.name)));
+        match &info.extends {
+            Some(extends) if !extends.is_empty() => {
+                out.push_str("    extends:\n");
+                for parent in extends {
+                    out.push_str(&format!("      - {}\n", scalar(parent)));
+                }
+            }
+            Some(_) => out.push_str("    extends: []\n"),
+            None if !options.omit_null => out.push_str("    extends: null\n"),
+            None => {}
+        }
+        if info.endpoints.is_empty() {
+            out.push_str("    endpoints: []\n");
+            continue;
+        }
+        out.push_str("    endpoints:\n");
+        for endpoint in &info.endpoints {
+            push_comments(&mut out, 6, comments.endpoints.get(&endpoint.fingerprint()));
+            out.push_str(&endpoint_item(endpoint, 6, options.omit_null));
+        }
+    }
+    out
+}
+
+/// Renders one endpoint as a block sequence item at `indent`.
+pub fn endpoint_item(endpoint: &WhitelistEndpoint, indent: usize, omit_null: bool) -> String {
+    let mut out = String::new();
+    let mut first = true;
+    for (key, value) in endpoint_fields(endpoint) {
+        if value.is_none() && omit_null {
+            continue;
+        }
+        push_field(&mut out, &mut first, indent, key, value);
+    }
+    for (key, value) in metadata_fields(endpoint) {
+        push_field(&mut out, &mut first, indent, key, Some(value));
+    }
+    if first {
+        out.push_str(&format!("{:indent$}- {{}}\n", "", indent = indent));
+    }
+    out
+}
+
+fn is_item(body: &str) -> bool {
+    body.starts_with("- ") || body == "-"
+}
+
+/// Locates the block-style `endpoints` list of whitelist `name` in a YAML
+/// whitelist. Returns the byte offset just past its last line and the
+/// indentation of its items, or `None` when the list is empty, written in
+/// flow style or not found.
+pub fn endpoints_end(text: &str, name: &str) -> Option<(usize, usize)> {
+    // (end offset, indent, body) of lines with content
+    let mut lines: Vec<(usize, usize, &str)> = Vec::new();
+    let mut offset = 0;
+    for raw in text.split_inclusive('\n') {
+        offset += raw.len();
+        let body = raw.trim();
+        if !body.is_empty() && !body.starts_with('#') {
+            lines.push((offset, indent_of(raw), body));
+        }
+    }
+
+    let top = lines.iter().position(|l| l.2.starts_with("whitelists:"))?;
+    let item_indent = lines.get(top + 1).filter(|l| is_item(l.2))?.1;
+    let mut i = top + 1;
+    while i < lines.len() && lines[i].1 == item_indent && is_item(lines[i].2) {
+        // The whitelist item spans up to the next item or dedent
+        let rest = lines[i].2[1..].trim_start();
+        let key_indent = item_indent + lines[i].2.len() - rest.len();
+        let mut end = i + 1;
+        while end < lines.len() && lines[end].1 > item_indent {
+            end += 1;
+        }
+        let key = |j: usize| if j == i { rest } else { lines[j].2 };
+        let keys: Vec<usize> = (i..end)
+            .filter(|&j| j == i || lines[j].1 == key_indent)
+            .collect();
+        let named = keys.iter().any(|&j| {
+            key(j)
+                .strip_prefix("name:")
+                .and_then(|v| serde_norway::from_str::<String>(v.trim()).ok())
+                .is_some_and(|v| v == name)
+        });
+        if named {
+            let at = *keys.iter().find(|&&j| key(j).starts_with("endpoints:"))?;
+            let value = key(at)["endpoints:".len()..].trim();
+            if !value.is_empty() && !value.starts_with('#') {
+                return None;
+            }
+            let first = lines.get(at + 1).filter(|l| at + 1 < end && is_item(l.2))?;
+            let endpoint_indent = first.1;
+            let mut last = at + 1;
+            while last + 1 < end
+                && (lines[last + 1].1 > endpoint_indent
+                    || (lines[last + 1].1 == endpoint_indent && is_item(lines[last + 1].2)))
+            {
+                last += 1;
+            }
+            return Some((lines[last].0, endpoint_indent));
+        }
+        i = end;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+# Egress policy for the build job
+# Owned by the platform team
+
+date: July 28th 2025
+signature: null
+whitelists:
+  # Main list loaded by scan/watch
+  - name: custom_whitelist
+    extends: null
+    endpoints:
+      # Source checkout
+      - domain: api.github.com
+        port: 443
+        protocol: TCP
+      # npm install
+      # (registry mirror)
+      - domain: registry.npmjs.org
+        port: 443
+        protocol: TCP
+      - ip: \"2a01:e0a::1\"
+        port: 9993
+        protocol: UDP
+";
+
+    #[test]
+    fn comments_survive_round_trip() {
+        let (whitelists, comments) = parse(SAMPLE).unwrap();
+        assert_eq!(whitelists.whitelists[0].endpoints.len(), 3);
+        assert_eq!(comments.header.len(), 2);
+
+        let yaml = to_yaml(&whitelists, &comments, &WriteOptions::default());
+        let (again, again_comments) = parse(&yaml).unwrap();
+        let mut expected = whitelists.clone();
+        expected.canonicalize();
+        assert_eq!(again, expected);
+        assert!(yaml.contains("# Egress policy for the build job"));
+        assert!(yaml.contains("  # Main list loaded by scan/watch\n  - name: custom_whitelist"));
+        assert!(yaml.contains(
+            "      # npm install\n      # (registry mirror)\n      - domain: registry.npmjs.org"
+        ));
+        assert_eq!(again_comments.endpoints.len(), 2);
+    }
+
+    #[test]
+    fn json_and_yaml_are_equivalent() {
+        let json = r#"{"date":"d","signature":null,"whitelists":[{"name":"custom_whitelist","extends":["base"],"endpoints":[{"domain":"*.github.com","ip":null,"port":443,"protocol":"TCP","as_number":null,"as_country":null,"as_owner":null,"process":null,"description":"Wildcard: yes"}]},{"name":"base","extends":null,"endpoints":[]}]}"#;
+        let whitelists = WhitelistsJSON::from_json(json).unwrap();
+        let yaml = to_yaml(&whitelists, &Comments::default(), &WriteOptions::default());
+        let (parsed, _) = parse(&yaml).unwrap();
+        let mut expected = whitelists.clone();
+        expected.canonicalize();
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn scalars_are_quoted_when_ambiguous() {
+        assert_eq!(scalar("TCP"), "TCP");
+        assert_eq!(scalar("api.github.com"), "api.github.com");
+        assert_eq!(scalar("*.github.com"), "\"*.github.com\"");
+        assert_eq!(scalar("no"), "\"no\"");
+        assert_eq!(scalar("140.82.114.3"), "\"140.82.114.3\"");
+    }
+}